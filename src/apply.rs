@@ -1,147 +1,449 @@
-use anyhow::{Context, Result};
+use anyhow::{bail, ensure, Context, Result};
 use cargo::{
-    core::{dependency::DepKind, resolver::CliFeatures, FeatureValue, Package, Workspace},
-    ops::{Packages, PublishOpts},
-    util::{cache_lock::CacheLockMode, toml_mut::manifest::LocalManifest},
+    core::{
+        dependency::DepKind, resolver::CliFeatures, Dependency, FeatureValue, Package, SourceId,
+        Workspace,
+    },
+    ops::{Packages, PublishOpts, RegistryOrIndex},
+    sources::{source::Source, IndexSummary},
+    util::{auth, cache_lock::CacheLockMode, IntoUrl},
 };
+use cargo_credential::Operation;
 
 use semver::Version;
+use termcolor::{Color, ColorChoice, ColorSpec, WriteColor};
 
 use std::{
-    collections::{BTreeMap, BTreeSet},
-    env::{self, current_dir},
+    collections::{BTreeMap, BTreeSet, VecDeque},
+    env,
     io::Write,
-    ops::Add,
-    path::Path,
+    path::{Path, PathBuf},
+    process::Command,
     str::FromStr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
     thread,
     time::{Duration, Instant},
 };
 
 use crate::{
+    changed,
     cli::{Apply, Args},
-    config, edit,
-    plan::{expand_plan, get_upstream, Planner, RemoveFeature},
-    registry,
+    config,
+    edit::{self, DepSource},
+    plan::{expand_plan, get_upstream, validate_plan, Planner, Publish, RemoveFeature},
+    registry, shared,
 };
 
-pub async fn handle_apply(args: Args, apply: Apply) -> Result<()> {
-    let path = current_dir()?;
+pub async fn handle_apply(args: Args, mut apply: Apply) -> Result<()> {
     let mut stdout = args.stdout();
-    let mut stderr = args.stderr();
+    let profile = Profile::new();
 
     let cargo_config = cargo::GlobalContext::default()?;
     cargo_config
         .shell()
         .set_verbosity(cargo::core::Verbosity::Quiet);
 
-    let workspace = Workspace::new(&path.join("Cargo.toml"), &cargo_config)?;
+    let workspace = Workspace::new(&args.manifest_path()?, &cargo_config)?;
+    let path = workspace.root().to_path_buf();
     let config = config::read_config(&path)?;
 
-    let workspace_crates = workspace
-        .members()
-        .map(|m| (m.name().as_str(), m))
-        .collect::<BTreeMap<_, _>>();
-
-    let upstream = get_upstream(&workspace, &mut stderr).await?;
+    let workspace_crates = shared::workspace_crates(&workspace)?;
 
-    let plan = std::fs::read_to_string(path.join("Plan.toml"))
-        .context("Can't find Plan.toml. Have your ran plan first?")?;
+    let plan = std::fs::read_to_string(path.join(&apply.plan)).with_context(|| {
+        format!(
+            "Can't find {}. Have your ran plan first?",
+            apply.plan.display()
+        )
+    })?;
     let mut plan: Planner = toml::from_str(&plan)?;
+
+    let now = Instant::now();
+    let upstream = get_upstream(&args, &workspace).await?;
     expand_plan(&workspace, &workspace_crates, &mut plan, &upstream).await?;
+    plan.crates.retain(|c| {
+        workspace_crates
+            .get(c.name.as_str())
+            .is_none_or(|m| !shared::is_excluded(&args, &workspace, m))
+    });
+    profile.add("registry lookup", now.elapsed());
+    let allow_published = apply.allow_published || apply.skip_published_check;
+    validate_plan(&args, &plan, &upstream, allow_published)?;
+
+    if let Some(set) = &apply.set {
+        if !plan.crates.iter().any(|c| c.set.as_ref() == Some(set)) {
+            bail!("no crates in {} are in set '{}'", apply.plan.display(), set);
+        }
+    }
+
+    let reg_or_index = reg_or_index(&apply)?;
 
     if apply.print {
-        list(&path, &cargo_config, &plan)?;
+        list(
+            &args,
+            &apply,
+            &path,
+            &cargo_config,
+            &plan,
+            reg_or_index.as_ref(),
+        )?;
         return Ok(());
     }
 
     let token = if apply.publish {
-        env::var("PARITY_PUBLISH_CRATESIO_TOKEN")
-            .context("PARITY_PUBLISH_CRATESIO_TOKEN must be set")?
+        registry_token(&apply, &cargo_config, reg_or_index.as_ref())?
     } else {
         String::new()
     };
 
-    writeln!(stdout, "rewriting manifests...")?;
+    let check_dirty = apply.publish && !apply.allow_dirty;
+    let dirty = if check_dirty {
+        changed::dirty_files(&path)?
+    } else {
+        Vec::new()
+    };
+    let mut originals = Vec::new();
+
+    if !apply.no_rewrite {
+        writeln!(stdout, "rewriting manifests...")?;
+        if config::config_path(&path).exists() {
+            writeln!(
+                stdout,
+                "applying Plan.config: {} edits to {} crates, {} crates removed",
+                config.edits(),
+                config.crates.len(),
+                config.remove_crates.len()
+            )?;
+        } else {
+            writeln!(
+                stdout,
+                "no Plan.config in {}, skipping config",
+                path.display()
+            )?;
+        }
+        originals = rewrite_or_restore(
+            &apply, &workspace, &path, &config, &plan, &upstream, &profile,
+        )?;
+    }
+
+    if check_dirty {
+        let unrelated = unrelated_changes(&dirty, &originals);
+        if !unrelated.is_empty() {
+            shared::restore_files(&originals)?;
+            let files = unrelated
+                .iter()
+                .map(|f| format!("    {}", f.strip_prefix(&path).unwrap_or(f).display()))
+                .collect::<Vec<_>>()
+                .join("\n");
+            bail!(
+                "working tree has uncommitted changes, commit them or pass --allow-dirty:\n{}",
+                files
+            );
+        }
+        // whatever is left dirty is our own rewriting, which cargo would refuse to publish
+        apply.allow_dirty = true;
+    }
 
-    config::apply_config(&workspace, &config)?;
+    if apply.publish {
+        publish(&args, &apply, &cargo_config, plan, &path, token, &profile)?;
+    } else if apply.package_only {
+        package(&args, &apply, &workspace, &plan, &profile)?;
+    }
 
-    let workspace_crates = workspace
-        .members()
-        .map(|m| (m.name().as_str(), m))
-        .collect::<BTreeMap<_, _>>();
+    if apply.profile {
+        profile.print(&args)?;
+    }
+
+    Ok(())
+}
+
+/// Wall clock time spent in each phase of apply, printed at the end with `--profile`.
+struct Profile {
+    start: Instant,
+    phases: Mutex<Vec<(String, Duration)>>,
+    crates: Mutex<Vec<(String, Duration)>>,
+}
+
+impl Profile {
+    fn new() -> Self {
+        Profile {
+            start: Instant::now(),
+            phases: Mutex::new(Vec::new()),
+            crates: Mutex::new(Vec::new()),
+        }
+    }
+
+    fn add(&self, phase: impl Into<String>, time: Duration) {
+        self.phases.lock().unwrap().push((phase.into(), time));
+    }
+
+    fn add_crate(&self, pkg: &Publish, time: Duration) {
+        let name = format!("{}-{}", pkg.name, pkg.to);
+        self.crates.lock().unwrap().push((name, time));
+    }
+
+    fn print(&self, args: &Args) -> Result<()> {
+        let mut stderr = args.stderr();
+        let phases = self.phases.lock().unwrap();
+        let mut crates = self.crates.lock().unwrap();
+
+        writeln!(stderr, "time spent:")?;
+        for (phase, time) in phases.iter() {
+            writeln!(stderr, "    {:<40}{:>8.1}s", phase, time.as_secs_f64())?;
+        }
+        writeln!(
+            stderr,
+            "    {:<40}{:>8.1}s",
+            "total",
+            self.start.elapsed().as_secs_f64()
+        )?;
+
+        if !crates.is_empty() {
+            let total = crates.iter().map(|(_, t)| *t).sum::<Duration>();
+            writeln!(
+                stderr,
+                "{} crates took {:.1}s to publish, the slowest were:",
+                crates.len(),
+                total.as_secs_f64()
+            )?;
+            crates.sort_by_key(|(_, time)| std::cmp::Reverse(*time));
+            for (name, time) in crates.iter().take(10) {
+                writeln!(stderr, "    {:<40}{:>8.1}s", name, time.as_secs_f64())?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Returns the files that were dirty before rewriting and that aren't just an earlier rewrite.
+///
+/// A dirty file is an earlier rewrite if it's one rewriting touches and rewriting it again
+/// didn't change it.
+fn unrelated_changes<'a>(
+    dirty: &'a [PathBuf],
+    originals: &[(PathBuf, Option<String>)],
+) -> Vec<&'a PathBuf> {
+    let canonical = |p: &Path| p.canonicalize().unwrap_or_else(|_| p.to_path_buf());
+
+    dirty
+        .iter()
+        .filter(|file| {
+            let file = canonical(file);
+            !originals.iter().any(|(path, contents)| {
+                canonical(path) == file
+                    && contents.is_some()
+                    && contents.as_deref() == std::fs::read_to_string(path).ok().as_deref()
+            })
+        })
+        .collect()
+}
+
+/// Rewrites the manifests, putting back every file that was touched if that fails part way
+/// through. Returns the original files.
+fn rewrite_or_restore(
+    apply: &Apply,
+    workspace: &Workspace,
+    path: &Path,
+    config: &config::Config,
+    plan: &Planner,
+    upstream: &BTreeMap<String, Vec<IndexSummary>>,
+    profile: &Profile,
+) -> Result<Vec<(PathBuf, Option<String>)>> {
+    let originals = shared::snapshot_files(workspace)?;
+    let rewrite = rewrite_manifests(apply, workspace, path, config, plan, upstream, profile);
+    if let Err(err) = rewrite {
+        shared::restore_files(&originals)?;
+        return Err(err.context("failed to rewrite manifests, they have been restored"));
+    }
+    Ok(originals)
+}
+
+/// Rewrites the workspace manifests so the crates in the plan are ready to publish.
+fn rewrite_manifests(
+    apply: &Apply,
+    workspace: &Workspace,
+    path: &Path,
+    config: &config::Config,
+    plan: &Planner,
+    upstream: &BTreeMap<String, Vec<IndexSummary>>,
+    profile: &Profile,
+) -> Result<()> {
+    let now = Instant::now();
+    config::apply_config(workspace, config)?;
+    profile.add("config apply", now.elapsed());
+    let now = Instant::now();
+
+    let workspace_crates = shared::workspace_crates(workspace)?;
+
+    let dep_source = if apply.registry_only {
+        DepSource::RegistryOnly
+    } else if apply.registry {
+        DepSource::Registry
+    } else {
+        DepSource::Path
+    };
+
+    edit::set_readme_desc(workspace, plan)?;
 
     let root_manifest = std::fs::read_to_string(workspace.root_manifest())?;
     let mut root_manifest = toml_edit::DocumentMut::from_str(&root_manifest)?;
+    let mut workspace_deps = BTreeMap::new();
+    let mut manifests = edit::Manifests::default();
     for pkg in &plan.crates {
         let Some(c) = workspace_crates.get(pkg.name.as_str()) else {
             continue;
         };
 
-        let mut manifest = LocalManifest::try_new(c.manifest_path())?;
-        edit::set_version(&mut manifest, &pkg.to)?;
+        let manifest_path = c.manifest_path();
+        edit::set_version(manifests.get(manifest_path)?, &pkg.to)?;
         //edit::set_description(&plan, &mut manifest, &pkg.name)?;
-        edit::set_readme_desc(&workspace, &plan)?;
 
         for remove_dep in &pkg.remove_dep {
-            edit::remove_dep(&workspace, &mut root_manifest, &mut manifest, remove_dep)?;
+            edit::remove_dep(
+                workspace,
+                &mut root_manifest,
+                &mut manifests,
+                manifest_path,
+                remove_dep,
+            )?;
         }
 
-        edit::rewrite_deps(
-            &workspace,
-            &path,
-            &plan,
-            &mut root_manifest,
-            &mut manifest,
+        let deps = edit::rewrite_deps(
+            workspace,
+            path,
+            plan,
+            &root_manifest,
+            manifests.get(manifest_path)?,
             &workspace_crates,
-            &upstream,
+            upstream,
             &pkg.rewrite_dep,
-            apply.registry,
+            dep_source,
+            apply.version_dev_deps,
         )?;
+        workspace_deps.extend(deps.into_iter().map(|dep| (dep.name.clone(), dep)));
 
         for remove_feature in &pkg.remove_feature {
-            edit::remove_feature(&mut manifest, remove_feature)?;
+            edit::remove_feature(
+                workspace,
+                &mut root_manifest,
+                &mut manifests,
+                manifest_path,
+                remove_feature,
+            )?;
         }
         for remove_feature in remove_dev_features(c) {
-            edit::remove_feature(&mut manifest, &remove_feature)?;
+            edit::remove_feature(
+                workspace,
+                &mut root_manifest,
+                &mut manifests,
+                manifest_path,
+                &remove_feature,
+            )?;
         }
+    }
+    manifests.write()?;
 
-        manifest.write()?;
-        std::fs::write(workspace.root_manifest(), &root_manifest.to_string())?;
+    for dep in workspace_deps.values() {
+        edit::bump_workspace_dependency(
+            path,
+            plan,
+            &mut root_manifest,
+            &workspace_crates,
+            upstream,
+            dep,
+            dep_source,
+        )?;
     }
+    std::fs::write(workspace.root_manifest(), &root_manifest.to_string())?;
+    profile.add("rewrite manifests", now.elapsed());
 
-    if !apply.publish {
-        return Ok(());
+    Ok(())
+}
+
+/// The registry to publish to, `None` being crates.io.
+fn reg_or_index(apply: &Apply) -> Result<Option<RegistryOrIndex>> {
+    Ok(match (&apply.registry_name, &apply.registry_url) {
+        (Some(name), _) => Some(RegistryOrIndex::Registry(name.clone())),
+        (None, Some(url)) => Some(RegistryOrIndex::Index(url.into_url()?)),
+        (None, None) => None,
+    })
+}
+
+/// The token for the registry being published to.
+///
+/// `PARITY_PUBLISH_CRATESIO_TOKEN` is only ever sent to crates.io. Other registries get an empty
+/// token so cargo resolves it from its own config and credential providers for each publish,
+/// this only checks up front that cargo has one.
+fn registry_token(
+    apply: &Apply,
+    config: &cargo::GlobalContext,
+    reg_or_index: Option<&RegistryOrIndex>,
+) -> Result<String> {
+    let sid = match reg_or_index {
+        None => {
+            return env::var("PARITY_PUBLISH_CRATESIO_TOKEN")
+                .context("PARITY_PUBLISH_CRATESIO_TOKEN must be set")
+        }
+        Some(RegistryOrIndex::Registry(name)) => SourceId::alt_registry(config, name)?,
+        Some(RegistryOrIndex::Index(url)) => SourceId::for_registry(url)?,
+    };
+
+    // cargo doesn't need a token for a dry run either
+    if !apply.dry_run {
+        auth::auth_token(config, &sid, None, Operation::Read, Vec::new(), false)
+            .with_context(|| format!("no token for {}", sid.display_registry_name()))?;
     }
 
-    publish(&args, &apply, &cargo_config, plan, &path, token)
+    Ok(String::new())
 }
 
 fn list(
+    args: &Args,
+    apply: &Apply,
     path: &std::path::PathBuf,
     cargo_config: &cargo::GlobalContext,
     plan: &Planner,
+    reg_or_index: Option<&RegistryOrIndex>,
 ) -> Result<(), anyhow::Error> {
+    let mut stdout = args.stdout();
     let workspace = Workspace::new(&path.join("Cargo.toml"), cargo_config)?;
     let _lock = cargo_config.acquire_package_cache_lock(CacheLockMode::DownloadExclusive)?;
-    let mut reg = registry::get_registry(&workspace)?;
+    let mut reg = registry::get_publish_registry(&workspace, reg_or_index)?;
     registry::download_crates(&mut reg, &workspace, false)?;
-    Ok(
-        for c in plan
-            .crates
-            .iter()
-            .filter(|c| {
-                workspace
-                    .members()
-                    .find(|m| m.name().as_str() == c.name)
-                    .map(|m| m.publish().is_some())
-                    .unwrap_or(false)
-            })
-            .filter(|c| !version_exists(&mut reg, &c.name, &c.to))
-        {
-            println!("{}@{}", c.name, c.to);
-        },
-    )
+
+    let (skipped, crates): (Vec<_>, Vec<_>) = plan
+        .crates
+        .iter()
+        .filter(|c| c.publish && in_set(apply, c))
+        .partition(|c| version_exists(&mut reg, &c.name, &c.to));
+
+    for c in &crates {
+        writeln!(stdout, "{}@{}", c.name, c.to)?;
+    }
+
+    let batches =
+        create_dependency_aware_batches(apply, &workspace, &crates, apply.batch_size as usize);
+    let levels = batches
+        .iter()
+        .map(|(level, _)| level + 1)
+        .max()
+        .unwrap_or(0);
+
+    // keep stdout to the list of crates so it can be piped
+    let mut stderr = args.stderr();
+    writeln!(
+        stderr,
+        "{} packages to publish, {} already published, {} batches over {} dependency levels",
+        crates.len(),
+        skipped.len(),
+        batches.len(),
+        levels
+    )?;
+
+    Ok(())
 }
 
 fn publish(
@@ -151,89 +453,990 @@ fn publish(
     plan: Planner,
     path: &Path,
     token: String,
+    profile: &Profile,
 ) -> Result<()> {
     let mut stdout = args.stdout();
-    let mut n = 1;
 
     let workspace = Workspace::new(&path.join("Cargo.toml"), config)?;
+    let reg_or_index = reg_or_index(apply)?;
+    let log_dir = apply.log_dir.as_ref().map(|dir| path.join(dir));
+    if let Some(dir) = &log_dir {
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("failed to create {}", dir.display()))?;
+    }
 
-    let _lock = config.acquire_package_cache_lock(CacheLockMode::DownloadExclusive)?;
-    let mut reg = registry::get_registry(&workspace)?;
-    registry::download_crates(&mut reg, &workspace, false)?;
+    let now = Instant::now();
+    let (skipped, crates): (Vec<_>, Vec<_>) = if apply.skip_published_check {
+        (
+            Vec::new(),
+            plan.crates
+                .iter()
+                .filter(|c| c.publish && in_set(apply, c))
+                .collect(),
+        )
+    } else {
+        let _lock = config.acquire_package_cache_lock(CacheLockMode::DownloadExclusive)?;
+        let mut reg = registry::get_publish_registry(&workspace, reg_or_index.as_ref())?;
+        registry::download_crates(&mut reg, &workspace, false)?;
+
+        plan.crates
+            .iter()
+            .filter(|c| c.publish && in_set(apply, c))
+            .partition(|pkg| version_exists(&mut reg, &pkg.name, &pkg.to))
+    };
+    profile.add("registry download", now.elapsed());
+    let total = crates.len();
+
+    let batches =
+        create_dependency_aware_batches(apply, &workspace, &crates, apply.batch_size as usize);
+
+    if apply.preflight {
+        let now = Instant::now();
+        preflight(
+            args,
+            path,
+            batches.first().and_then(|(_, b)| b.first().copied()),
+        )?;
+        profile.add("preflight", now.elapsed());
+    }
+
+    writeln!(
+        stdout,
+        "Publishing {} packages ({} skipped) in {} batches",
+        total,
+        skipped.len(),
+        batches.len()
+    )?;
+
+    // Batches on the same dependency level don't depend on each other so they can be published
+    // together.
+    let mut waves: Vec<(usize, Vec<&Publish>)> = Vec::new();
+    for (level, batch) in batches {
+        match waves.last_mut() {
+            Some((wave_level, wave))
+                if *wave_level == level
+                    && wave.len() < apply.batch_size as usize * apply.parallel_batches as usize =>
+            {
+                wave.extend(batch)
+            }
+            _ => waves.push((level, batch)),
+        }
+    }
+
+    let deps = workspace
+        .members()
+        .map(|c| {
+            let deps = c
+                .dependencies()
+                .iter()
+                .filter(|d| published_first(apply, d))
+                .map(|d| d.package_name().to_string())
+                .collect();
+            (c.name().to_string(), deps)
+        })
+        .collect();
+
+    let publisher = Publisher {
+        args,
+        apply,
+        token: &token,
+        reg_or_index: reg_or_index.as_ref(),
+        profile,
+        log_dir,
+        published: Mutex::new(BTreeSet::new()),
+        deps,
+        failed: Mutex::new(Vec::new()),
+        skipped: Mutex::new(Vec::new()),
+        n: AtomicUsize::new(1),
+        total,
+    };
+    let concurrency = apply.max_concurrent as usize * apply.parallel_batches.max(1) as usize;
+
+    for (i, (_, wave)) in waves.iter().enumerate() {
+        let last = i + 1 == waves.len();
+
+        let now = Instant::now();
+        if concurrency == 1 && publisher.log_dir.is_none() {
+            publisher.publish_sequential(config, &workspace, wave)?;
+        } else {
+            publisher.publish_parallel(path, wave, concurrency)?;
+        }
+        profile.add(format!("batch {} publish", i + 1), now.elapsed());
+
+        if !last && !apply.dry_run {
+            let now = Instant::now();
+            let wave = wave
+                .iter()
+                .copied()
+                .filter(|pkg| !publisher.gave_up(&pkg.name))
+                .collect::<Vec<_>>();
+            wait_for_index(args, apply, &workspace, reg_or_index.as_ref(), &wave)?;
+            profile.add(format!("batch {} wait for index", i + 1), now.elapsed());
+        }
+
+        if !last && apply.batch_delay > 0 {
+            writeln!(
+                stdout,
+                "waiting {}s before publishing next batch...",
+                apply.batch_delay
+            )?;
+            thread::sleep(Duration::from_secs(apply.batch_delay));
+            profile.add(
+                format!("batch {} delay", i + 1),
+                Duration::from_secs(apply.batch_delay),
+            );
+        }
+    }
+
+    if apply.keep_going {
+        publisher.print_summary()?;
+    }
+
+    Ok(())
+}
 
-    let skipped = plan
+/// Packages the crates that would be published and copies the .crate files to `--output-dir` so
+/// they can be inspected and uploaded separately.
+///
+/// The crates that are verified are packaged by one `cargo package` and the ones with
+/// `verify = false` by another, so crates can depend on workspace members in the same group that
+/// aren't published yet without turning off verification for everything.
+fn package(
+    args: &Args,
+    apply: &Apply,
+    workspace: &Workspace,
+    plan: &Planner,
+    profile: &Profile,
+) -> Result<()> {
+    let mut stdout = args.stdout();
+    let path = workspace.root();
+
+    let crates = plan
         .crates
         .iter()
-        .filter(|c| c.publish)
-        .filter(|pkg| version_exists(&mut reg, &pkg.name, &pkg.to))
-        .count();
-    let total = plan.crates.iter().filter(|c| c.publish).count() - skipped;
+        .filter(|c| c.publish && in_set(apply, c))
+        .collect::<Vec<_>>();
+    // no --package would package the default members instead
+    ensure!(!crates.is_empty(), "the plan has no crates to package");
+
+    let output_dir = path.join(
+        apply
+            .output_dir
+            .as_ref()
+            .context("--output-dir is required")?,
+    );
+    std::fs::create_dir_all(&output_dir)
+        .with_context(|| format!("failed to create {}", output_dir.display()))?;
+    let package_dir = workspace.target_dir().into_path_unlocked().join("package");
+    let reg_or_index = reg_or_index(apply)?;
+
+    writeln!(stdout, "Packaging {} packages...", crates.len())?;
+    let now = Instant::now();
+
+    let (verified, unverified): (Vec<_>, Vec<_>) = crates
+        .iter()
+        .copied()
+        .partition(|c| c.verify && !apply.no_verify);
+
+    for (group, verify) in [(&verified, true), (&unverified, false)] {
+        if group.is_empty() {
+            continue;
+        }
+
+        let mut cmd = Command::new(env::var_os("CARGO").unwrap_or_else(|| "cargo".into()));
+        // the manifests have just been rewritten
+        cmd.current_dir(path).arg("package").arg("--allow-dirty");
+        for pkg in group {
+            cmd.arg("--package").arg(&pkg.name);
+        }
+        if !verify {
+            cmd.arg("--no-verify");
+        }
+        match &reg_or_index {
+            Some(RegistryOrIndex::Registry(name)) => {
+                cmd.arg("--registry").arg(name);
+            }
+            Some(RegistryOrIndex::Index(url)) => {
+                cmd.arg("--index").arg(url.as_str());
+            }
+            None => (),
+        }
+
+        let output = cmd.output()?;
+        if !output.status.success() {
+            bail!(
+                "failed to package crates:\n{}",
+                strip_ansi(&String::from_utf8_lossy(&output.stderr))
+            );
+        }
+    }
+    profile.add("package", now.elapsed());
+
+    for pkg in &crates {
+        let file = format!("{}-{}.crate", pkg.name, pkg.to);
+        std::fs::copy(package_dir.join(&file), output_dir.join(&file))
+            .with_context(|| format!("failed to copy {} to {}", file, output_dir.display()))?;
+        writeln!(stdout, "{}", file)?;
+    }
 
     writeln!(
         stdout,
-        "Publishing {} packages ({} skipped)",
-        total, skipped
+        "wrote {} packages to {} ({}s)",
+        crates.len(),
+        output_dir.display(),
+        now.elapsed().as_secs()
     )?;
 
-    drop(_lock);
+    Ok(())
+}
 
-    let mut iter = plan
-        .crates
+/// Fails early if publishing would fail because of the toolchain rather than a crate.
+///
+/// Checks the components and targets asked for by the toolchain file are installed and builds
+/// `first`, the first crate that will be published.
+fn preflight(args: &Args, path: &Path, first: Option<&Publish>) -> Result<()> {
+    args.progress("checking toolchain...")?;
+
+    let file = ["rust-toolchain.toml", "rust-toolchain"]
+        .into_iter()
+        .map(|f| path.join(f))
+        .find(|f| f.exists());
+    // a plain rust-toolchain file only names the channel
+    let toolchain = file
+        .and_then(|f| std::fs::read_to_string(f).ok())
+        .and_then(|f| toml::from_str::<toml::Table>(&f).ok())
+        .and_then(|t| t.get("toolchain")?.as_table().cloned())
+        .unwrap_or_default();
+
+    let mut missing = Vec::new();
+    for (key, kind) in [("components", "component"), ("targets", "target")] {
+        let wanted = toolchain
+            .get(key)
+            .and_then(|v| v.as_array())
+            .into_iter()
+            .flatten()
+            .filter_map(|v| v.as_str())
+            .collect::<Vec<_>>();
+        if wanted.is_empty() {
+            continue;
+        }
+
+        let res = Command::new("rustup")
+            .current_dir(path)
+            .arg(kind)
+            .arg("list")
+            .arg("--installed")
+            .output()
+            .context("failed to run rustup")?;
+        ensure!(
+            res.status.success(),
+            "rustup {} list failed: {}",
+            kind,
+            String::from_utf8_lossy(&res.stderr).trim()
+        );
+        let installed = String::from_utf8_lossy(&res.stdout);
+
+        for w in wanted {
+            // components are listed with the host triple appended
+            let found = installed
+                .lines()
+                .any(|i| i == w || i.strip_prefix(w).is_some_and(|i| i.starts_with('-')));
+            if !found {
+                missing.push(format!("{} {}", kind, w));
+            }
+        }
+    }
+    if !missing.is_empty() {
+        bail!(
+            "the toolchain is missing what rust-toolchain.toml asks for:\n    {}",
+            missing.join("\n    ")
+        );
+    }
+
+    let Some(first) = first else {
+        return Ok(());
+    };
+    args.progress(format_args!("checking {} builds...", first.name))?;
+    let res = Command::new(env::var_os("CARGO").unwrap_or_else(|| "cargo".into()))
+        .current_dir(path)
+        .arg("check")
+        .arg("--package")
+        .arg(&first.name)
+        .output()
+        .context("failed to run cargo check")?;
+    ensure!(
+        res.status.success(),
+        "{} failed to build, fix the build environment before publishing:\n{}",
+        first.name,
+        String::from_utf8_lossy(&res.stderr).trim()
+    );
+
+    Ok(())
+}
+
+/// Whether `pkg` is in the release set being applied, every crate is when no set is given.
+fn in_set(apply: &Apply, pkg: &Publish) -> bool {
+    apply.set.is_none() || pkg.set == apply.set
+}
+
+/// Wait for the published crates to show up in the registry index so crates in later batches
+/// that depend on them can resolve them.
+fn wait_for_index(
+    args: &Args,
+    apply: &Apply,
+    workspace: &Workspace,
+    reg_or_index: Option<&RegistryOrIndex>,
+    crates: &[&Publish],
+) -> Result<()> {
+    let config = workspace.gctx();
+    let mut stdout = args.stdout();
+    let mut stderr = args.stderr();
+    let start = Instant::now();
+    let mut pending = crates.to_vec();
+
+    writeln!(
+        stdout,
+        "waiting for {} packages to be indexed...",
+        pending.len()
+    )?;
+
+    loop {
+        {
+            let _lock = config.acquire_package_cache_lock(CacheLockMode::DownloadExclusive)?;
+            let mut reg = registry::get_publish_registry(workspace, reg_or_index)?;
+            for pkg in &pending {
+                let _ = registry::get_crate(&mut reg, pkg.name.as_str().into());
+            }
+            reg.block_until_ready()?;
+            pending.retain(|pkg| !version_exists(&mut reg, &pkg.name, &pkg.to));
+        }
+
+        if pending.is_empty() {
+            return Ok(());
+        }
+
+        if start.elapsed() >= Duration::from_secs(apply.poll_timeout) {
+            stderr.set_color(ColorSpec::new().set_fg(Some(Color::Yellow)).set_bold(true))?;
+            write!(stderr, "warning: ")?;
+            stderr.set_color(&ColorSpec::new())?;
+            writeln!(
+                stderr,
+                "timed out waiting for packages to be indexed: {}",
+                pending
+                    .iter()
+                    .map(|c| format!("{}@{}", c.name, c.to))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )?;
+            return Ok(());
+        }
+
+        thread::sleep(Duration::from_secs(apply.poll_interval));
+    }
+}
+
+struct Publisher<'a> {
+    args: &'a Args,
+    apply: &'a Apply,
+    token: &'a str,
+    reg_or_index: Option<&'a RegistryOrIndex>,
+    profile: &'a Profile,
+    /// Where to write each crate's cargo output
+    log_dir: Option<PathBuf>,
+    /// Crates published during this run, which may not be in the index yet
+    published: Mutex<BTreeSet<String>>,
+    /// The workspace members each member needs published first
+    deps: BTreeMap<String, Vec<String>>,
+    /// Crates that failed to publish with --keep-going and why
+    failed: Mutex<Vec<(String, String)>>,
+    /// Crates not attempted with --keep-going and the failed dependency that caused it
+    skipped: Mutex<Vec<(String, String)>>,
+    n: AtomicUsize,
+    total: usize,
+}
+
+/// Why cargo failed to publish a crate, along with cargo's output.
+#[derive(Debug)]
+enum PublishError {
+    /// The registry already has this version of the crate
+    AlreadyPublished(String),
+    /// A crate published earlier in the run is not in the index yet
+    IndexLag { dep: String, output: String },
+    /// The registry didn't accept the token
+    Auth(String),
+    /// The registry is limiting how fast we can publish
+    RateLimit(String),
+    /// The crate didn't build when verifying it
+    Build(String),
+    /// Anything else
+    Other(String),
+    /// Cargo couldn't be run or its output couldn't be written out
+    Io(std::io::Error),
+}
+
+impl PublishError {
+    /// Works out why cargo failed from its output.
+    fn classify(output: String, published: &BTreeSet<String>) -> Self {
+        if already_published(&output) {
+            return PublishError::AlreadyPublished(output);
+        }
+        if let Some(dep) = missing_dep(&output).filter(|dep| published.contains(*dep)) {
+            return PublishError::IndexLag {
+                dep: dep.to_string(),
+                output,
+            };
+        }
+
+        if [
+            "(status 401",
+            "(status 403",
+            "invalid token",
+            "not logged in",
+            "no token found",
+        ]
         .iter()
-        .filter(|c| c.publish)
-        .filter(|c| !version_exists(&mut reg, &c.name, &c.to))
-        .peekable();
-    while let Some(pkg) = iter.next() {
-        write!(
-            stdout,
-            "({:3<}/{:3<}) publishing {}-{}...",
-            n, total, pkg.name, pkg.to
-        )?;
-        stdout.flush()?;
+        .any(|s| output.contains(s))
+        {
+            PublishError::Auth(output)
+        } else if output.contains("(status 429") {
+            PublishError::RateLimit(output)
+        } else if output.contains("could not compile") || output.contains("failed to verify") {
+            PublishError::Build(output)
+        } else {
+            PublishError::Other(output)
+        }
+    }
+
+    /// What cargo printed when it failed.
+    fn output(&self) -> &str {
+        match self {
+            PublishError::AlreadyPublished(output)
+            | PublishError::IndexLag { output, .. }
+            | PublishError::Auth(output)
+            | PublishError::RateLimit(output)
+            | PublishError::Build(output)
+            | PublishError::Other(output) => output,
+            PublishError::Io(_) => "",
+        }
+    }
+}
 
-        n += 1;
+impl std::fmt::Display for PublishError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let msg = match self {
+            PublishError::AlreadyPublished(_) => "already published",
+            PublishError::IndexLag { dep, .. } => {
+                return write!(f, "{} was published but is not in the index yet", dep)
+            }
+            PublishError::Auth(_) => "the registry rejected the token",
+            PublishError::RateLimit(_) => "rate limited by the registry",
+            PublishError::Build(_) => "failed to build",
+            PublishError::Other(_) => "cargo publish failed",
+            PublishError::Io(e) => return write!(f, "failed to run cargo publish: {}", e),
+        };
 
-        let wait = Duration::from_secs(60);
-        let now = Instant::now();
+        // cargo's error rather than everything it printed
+        let output = self.output();
+        let line = output
+            .lines()
+            .find_map(|l| l.trim().strip_prefix("error: "))
+            .or_else(|| output.lines().find(|l| !l.trim().is_empty()));
+        match line {
+            Some(line) => write!(f, "{}: {}", msg, line.trim()),
+            None => f.write_str(msg),
+        }
+    }
+}
+
+impl std::error::Error for PublishError {}
+
+impl From<std::io::Error> for PublishError {
+    fn from(e: std::io::Error) -> Self {
+        PublishError::Io(e)
+    }
+}
 
-        let opts = PublishOpts {
-            gctx: config,
-            token: Some(token.clone().into()),
-            verify: pkg.verify && !apply.dry_run && !apply.no_verify,
-            allow_dirty: apply.allow_dirty,
-            jobs: None,
-            keep_going: false,
-            to_publish: Packages::Packages(vec![pkg.name.clone()]),
-            targets: Vec::new(),
-            dry_run: apply.dry_run,
-            cli_features: CliFeatures::new_all(false),
-            reg_or_index: None,
+/// Whether `err` is a [`PublishError`] matching `kind`.
+fn is_publish_error(err: &anyhow::Error, kind: impl Fn(&PublishError) -> bool) -> bool {
+    err.downcast_ref::<PublishError>().is_some_and(kind)
+}
+
+impl Publisher<'_> {
+    fn classify(&self, output: String) -> PublishError {
+        PublishError::classify(output, &self.published.lock().unwrap())
+    }
+
+    /// Whether a crate failed or was skipped with --keep-going.
+    fn gave_up(&self, name: &str) -> bool {
+        self.failed.lock().unwrap().iter().any(|(n, _)| n == name)
+            || self.skipped.lock().unwrap().iter().any(|(n, _)| n == name)
+    }
+
+    /// With --keep-going, skips `pkg` if one of its dependencies failed or was skipped.
+    fn skip_failed_dep(&self, pkg: &Publish) -> Result<bool> {
+        if !self.apply.keep_going {
+            return Ok(false);
+        }
+        let Some(dep) = self
+            .deps
+            .get(&pkg.name)
+            .into_iter()
+            .flatten()
+            .find(|d| self.gave_up(d))
+        else {
+            return Ok(false);
         };
-        cargo::ops::publish(&workspace, &opts)?;
 
-        writeln!(stdout, " ({}s)", (Instant::now() - now).as_secs())?;
+        writeln!(
+            self.args.stdout(),
+            "skipping {}-{}, it depends on {} which failed",
+            pkg.name,
+            pkg.to,
+            dep
+        )?;
+        self.skipped
+            .lock()
+            .unwrap()
+            .push((pkg.name.clone(), dep.clone()));
+        Ok(true)
+    }
+
+    /// Records a failed crate with --keep-going, otherwise returns the error.
+    fn fail(&self, pkg: &Publish, err: anyhow::Error) -> Result<()> {
+        if !self.apply.keep_going {
+            return Err(err);
+        }
+        writeln!(
+            self.args.stderr(),
+            "failed to publish {}: {:#}",
+            pkg.name,
+            err
+        )?;
+        self.failed.lock().unwrap().push((
+            pkg.name.clone(),
+            err.to_string().lines().next().unwrap_or("").into(),
+        ));
+        Ok(())
+    }
+
+    fn print_summary(&self) -> Result<()> {
+        let mut stdout = self.args.stdout();
+        let published = self.published.lock().unwrap();
+        let failed = self.failed.lock().unwrap();
+        let skipped = self.skipped.lock().unwrap();
+
+        writeln!(stdout, "published {} packages", published.len())?;
+        if !failed.is_empty() {
+            writeln!(stdout, "failed to publish {} packages:", failed.len())?;
+            for (name, err) in failed.iter() {
+                writeln!(stdout, "    {}: {}", name, err)?;
+            }
+        }
+        if !skipped.is_empty() {
+            writeln!(
+                stdout,
+                "skipped {} packages because a dependency failed:",
+                skipped.len()
+            )?;
+            for (name, dep) in skipped.iter() {
+                writeln!(stdout, "    {} (depends on {})", name, dep)?;
+            }
+        }
+
+        if !failed.is_empty() {
+            bail!(
+                "failed to publish {} packages, {} skipped",
+                failed.len(),
+                skipped.len()
+            );
+        }
+        Ok(())
+    }
+
+    /// Runs `publish` again while it fails with `IndexLag`, up to `--index-lag-retries` times.
+    fn retry_index_lag<T>(
+        &self,
+        pkg: &Publish,
+        mut publish: impl FnMut() -> Result<T>,
+    ) -> Result<T> {
+        let mut wait = Duration::from_secs(self.apply.poll_interval.max(1));
+        let max_wait = Duration::from_secs(self.apply.poll_timeout.max(1));
+        let mut retries = 0;
 
-        if iter.peek().is_some() {
-            if let Some(delay) = now.add(wait).checked_duration_since(now) {
-                thread::sleep(delay);
+        loop {
+            match publish() {
+                Err(e)
+                    if self.apply.continue_on_index_lag
+                        && retries < self.apply.index_lag_retries
+                        && is_publish_error(&e, |e| matches!(e, PublishError::IndexLag { .. })) =>
+                {
+                    retries += 1;
+                    writeln!(
+                        self.args.stderr(),
+                        "{}: {}, retrying in {}s ({}/{})",
+                        pkg.name,
+                        e,
+                        wait.as_secs(),
+                        retries,
+                        self.apply.index_lag_retries
+                    )?;
+                    thread::sleep(wait);
+                    wait = (wait * 2).min(max_wait);
+                }
+                res => return res,
             }
         }
     }
 
+    fn publish_sequential(
+        &self,
+        config: &cargo::GlobalContext,
+        workspace: &Workspace,
+        crates: &[&Publish],
+    ) -> Result<()> {
+        let apply = self.apply;
+        let mut stdout = self.args.stdout();
+        let mut iter = crates.iter().peekable();
+
+        while let Some(pkg) = iter.next() {
+            if self.skip_failed_dep(pkg)? {
+                continue;
+            }
+
+            write!(
+                stdout,
+                "({:3<}/{:3<}) publishing {}-{}...",
+                self.n.fetch_add(1, Ordering::SeqCst),
+                self.total,
+                pkg.name,
+                pkg.to
+            )?;
+            stdout.flush()?;
+
+            let now = Instant::now();
+
+            let opts = PublishOpts {
+                gctx: config,
+                token: (!self.token.is_empty()).then(|| self.token.to_string().into()),
+                verify: pkg.verify && !apply.dry_run && !apply.no_verify,
+                allow_dirty: apply.allow_dirty,
+                jobs: None,
+                keep_going: false,
+                to_publish: Packages::Packages(vec![pkg.name.clone()]),
+                targets: Vec::new(),
+                dry_run: apply.dry_run,
+                cli_features: CliFeatures::new_all(false),
+                reg_or_index: self.reg_or_index.cloned(),
+            };
+            let res = self.retry_index_lag(pkg, || {
+                cargo::ops::publish(workspace, &opts).map_err(|e| {
+                    match self.classify(format!("{:#}", e)) {
+                        // keep cargo's error when there's nothing to add to it
+                        PublishError::Other(_) => e,
+                        err => err.into(),
+                    }
+                })
+            });
+            match res {
+                Ok(()) => {
+                    self.published.lock().unwrap().insert(pkg.name.clone());
+                }
+                Err(e)
+                    if is_publish_error(&e, |e| matches!(e, PublishError::AlreadyPublished(_))) =>
+                {
+                    writeln!(stdout, " already published, skipping")?;
+                    continue;
+                }
+                Err(e) => {
+                    writeln!(stdout, " failed")?;
+                    self.fail(pkg, e)?;
+                    continue;
+                }
+            }
+
+            writeln!(stdout, " ({}s)", (Instant::now() - now).as_secs())?;
+            self.profile.add_crate(pkg, now.elapsed());
+
+            if iter.peek().is_some() && apply.between_crate_delay > 0 {
+                thread::sleep(Duration::from_secs(apply.between_crate_delay));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn publish_parallel(&self, path: &Path, crates: &[&Publish], concurrency: usize) -> Result<()> {
+        let queue = Mutex::new(crates.iter().copied().collect::<VecDeque<_>>());
+        let failed = Mutex::new(Vec::new());
+
+        thread::scope(|s| {
+            let workers = (0..concurrency.min(crates.len()))
+                .map(|_| {
+                    s.spawn(|| -> Result<()> {
+                        let mut stdout = self.args.stdout();
+
+                        loop {
+                            let Some(pkg) = queue.lock().unwrap().pop_front() else {
+                                return Ok(());
+                            };
+                            if self.skip_failed_dep(pkg)? {
+                                continue;
+                            }
+
+                            writeln!(
+                                stdout,
+                                "({:3<}/{:3<}) publishing {}-{}...",
+                                self.n.fetch_add(1, Ordering::SeqCst),
+                                self.total,
+                                pkg.name,
+                                pkg.to
+                            )?;
+
+                            let now = Instant::now();
+
+                            let res = self.retry_index_lag(pkg, || {
+                                Ok(self.publish_with_subprocess(path, pkg)?)
+                            });
+                            match res {
+                                Ok(()) => {
+                                    self.published.lock().unwrap().insert(pkg.name.clone());
+                                    writeln!(
+                                        stdout,
+                                        "published {}-{} ({}s)",
+                                        pkg.name,
+                                        pkg.to,
+                                        (Instant::now() - now).as_secs()
+                                    )?;
+                                    self.profile.add_crate(pkg, now.elapsed());
+                                }
+                                Err(e)
+                                    if is_publish_error(&e, |e| {
+                                        matches!(e, PublishError::AlreadyPublished(_))
+                                    }) =>
+                                {
+                                    writeln!(
+                                        stdout,
+                                        "{}-{} already published, skipping",
+                                        pkg.name, pkg.to
+                                    )?;
+                                    continue;
+                                }
+                                Err(e) if self.apply.keep_going => self.fail(pkg, e)?,
+                                Err(e) => failed.lock().unwrap().push((pkg.name.clone(), e)),
+                            }
+
+                            let more = !queue.lock().unwrap().is_empty();
+                            if more && self.apply.between_crate_delay > 0 {
+                                thread::sleep(Duration::from_secs(self.apply.between_crate_delay));
+                            }
+                        }
+                    })
+                })
+                .collect::<Vec<_>>();
+
+            workers
+                .into_iter()
+                .try_for_each(|w| w.join().expect("publish thread panicked"))
+        })?;
+
+        let failed = failed.into_inner().unwrap();
+        if !failed.is_empty() {
+            let mut stderr = self.args.stderr();
+            for (name, err) in &failed {
+                writeln!(stderr, "failed to publish {}: {:#}", name, err)?;
+            }
+            bail!("failed to publish {} packages", failed.len());
+        }
+
+        Ok(())
+    }
+
+    fn publish_with_subprocess(&self, path: &Path, pkg: &Publish) -> Result<(), PublishError> {
+        let apply = self.apply;
+        let mut cmd = Command::new(env::var_os("CARGO").unwrap_or_else(|| "cargo".into()));
+        cmd.current_dir(path)
+            .arg("publish")
+            .arg("--package")
+            .arg(&pkg.name);
+
+        if !self.token.is_empty() {
+            cmd.env("CARGO_REGISTRY_TOKEN", self.token);
+        }
+        // cargo's output is captured so it can't tell if it ends up on a terminal, --color
+        // has already decided that for us
+        let color = match self.args.color {
+            ColorChoice::Never => "never",
+            _ => "always",
+        };
+        cmd.env("CARGO_TERM_COLOR", color);
+        if apply.dry_run {
+            cmd.arg("--dry-run");
+        }
+        if !pkg.verify || apply.dry_run || apply.no_verify {
+            cmd.arg("--no-verify");
+        }
+        if apply.allow_dirty {
+            cmd.arg("--allow-dirty");
+        }
+        match self.reg_or_index {
+            Some(RegistryOrIndex::Registry(name)) => {
+                cmd.arg("--registry").arg(name);
+            }
+            Some(RegistryOrIndex::Index(url)) => {
+                cmd.arg("--index").arg(url.as_str());
+            }
+            None => (),
+        }
+
+        let output = cmd.output()?;
+
+        let mut stderr = self.args.stderr();
+        for line in String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .chain(String::from_utf8_lossy(&output.stderr).lines())
+        {
+            writeln!(stderr, "[cargo] {}: {}", pkg.name, line)?;
+        }
+        if let Some(dir) = &self.log_dir {
+            write_log(dir, pkg, &output)?;
+        }
+
+        if !output.status.success() {
+            let err = strip_ansi(&String::from_utf8_lossy(&output.stderr));
+            return Err(self.classify(err));
+        }
+
+        Ok(())
+    }
+}
+
+/// Appends the output of `cargo publish` to the crate's log, so retries are kept.
+fn write_log(dir: &Path, pkg: &Publish, output: &std::process::Output) -> std::io::Result<()> {
+    let mut log = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(dir.join(format!("{}.log", pkg.name)))?;
+
+    writeln!(
+        log,
+        "==> cargo publish {}-{} ({})",
+        pkg.name, pkg.to, output.status
+    )?;
+    log.write_all(strip_ansi(&String::from_utf8_lossy(&output.stdout)).as_bytes())?;
+    log.write_all(strip_ansi(&String::from_utf8_lossy(&output.stderr)).as_bytes())?;
     Ok(())
 }
 
-fn version_exists(reg: &mut cargo::sources::RegistrySource, name: &str, ver: &str) -> bool {
-    let c = registry::get_crate(reg, name.to_string().into());
-    let ver = Version::parse(ver).unwrap();
+/// Removes the escape sequences cargo colors its output with so errors can be matched.
+fn strip_ansi(s: &str) -> String {
+    let mut ret = String::with_capacity(s.len());
+    let mut chars = s.chars();
+
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            // CSI sequences end with a letter
+            chars.by_ref().find(|c| c.is_ascii_alphabetic());
+        } else {
+            ret.push(c);
+        }
+    }
+
+    ret
+}
+
+/// The dependency cargo couldn't find in the registry if that's why it failed.
+fn missing_dep(err: &str) -> Option<&str> {
+    [
+        "no matching package named `",
+        "failed to select a version for the requirement `",
+    ]
+    .into_iter()
+    .find_map(|pattern| {
+        let rest = &err[err.find(pattern)? + pattern.len()..];
+        rest.split(['`', ' ']).next()
+    })
+}
+
+/// Whether cargo failed to publish a crate because that version is already in the registry.
+///
+/// Only cargo's own check of the index and the registry rejecting the upload count, other errors
+/// can say something "already exists" too.
+fn already_published(err: &str) -> bool {
+    err.lines().any(|line| {
+        (line.contains("already exists on") && line.ends_with(" index"))
+            || line.contains("is already uploaded")
+            || (line.contains("(status 400") && line.ends_with("already exists"))
+    })
+}
+
+/// Whether a dependency has to be published before the crate depending on it. Cargo strips path
+/// dev dependencies without a version, which is all of them unless `--version-dev-deps` is passed.
+fn published_first(apply: &Apply, dep: &Dependency) -> bool {
+    dep.kind() != DepKind::Development
+        || (apply.version_dev_deps && dep.source_id().is_path() && dep.specified_req())
+}
+
+/// Splits the crates into batches where every crate only depends on crates in earlier batches.
+///
+/// Returns the dependency level of each batch along with the crates in it.
+fn create_dependency_aware_batches<'a>(
+    apply: &Apply,
+    workspace: &Workspace,
+    crates: &[&'a Publish],
+    batch_size: usize,
+) -> Vec<(usize, Vec<&'a Publish>)> {
+    let mut levels = BTreeMap::<&str, usize>::new();
+
+    loop {
+        let mut did_something = false;
+
+        for pkg in crates {
+            let Some(member) = workspace.members().find(|m| m.name().as_str() == pkg.name) else {
+                continue;
+            };
+
+            let level = member
+                .dependencies()
+                .iter()
+                .filter(|d| published_first(apply, d))
+                .filter(|d| crates.iter().any(|c| c.name == d.package_name().as_str()))
+                .map(|d| levels.get(d.package_name().as_str()).map_or(1, |l| l + 1))
+                .max()
+                .unwrap_or(0);
+
+            if levels.get(pkg.name.as_str()) != Some(&level) {
+                levels.insert(pkg.name.as_str(), level);
+                did_something = true;
+            }
+        }
 
-    if let Ok(c) = c {
-        if c.iter().any(|v| v.as_summary().version() == &ver) {
-            return true;
+        if !did_something {
+            break;
         }
     }
 
-    false
+    let mut by_level = BTreeMap::<usize, Vec<&Publish>>::new();
+    for pkg in crates {
+        let level = levels.get(pkg.name.as_str()).copied().unwrap_or(0);
+        by_level.entry(level).or_default().push(pkg);
+    }
+
+    by_level
+        .into_iter()
+        .flat_map(|(level, crates)| {
+            crates
+                .chunks(batch_size)
+                .map(|batch| (level, batch.to_vec()))
+                .collect::<Vec<_>>()
+        })
+        .collect()
+}
+
+fn version_exists(reg: &mut cargo::sources::RegistrySource, name: &str, ver: &str) -> bool {
+    let c = registry::get_crate(reg, name.to_string().into());
+    let ver = Version::parse(ver).unwrap();
+
+    c.is_ok_and(|c| registry::has_version(&c, &ver))
 }
 
 fn remove_dev_features(member: &Package) -> Vec<RemoveFeature> {
@@ -272,3 +1475,221 @@ fn remove_dev_features(member: &Package) -> Vec<RemoveFeature> {
 
     remove
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plan::{Options, Publish, RewriteDep};
+    use crate::shared::test::{gctx, write_tree};
+    use clap::Parser;
+
+    const AUTH: &str = r#"    Updating crates.io index
+   Packaging foo v0.1.0 (/ws/foo)
+   Uploading foo v0.1.0 (/ws/foo)
+error: failed to publish to registry at https://crates.io
+
+Caused by:
+  the remote server responded with an error (status 403 Forbidden): this crate exists but you don't seem to be an owner. If you believe this is a mistake, perhaps you need to accept an invitation to be an owner before publishing.
+"#;
+
+    const NO_TOKEN: &str = r#"    Updating crates.io index
+error: no token found, please run `cargo login`
+or use environment variable CARGO_REGISTRY_TOKEN
+"#;
+
+    const RATE_LIMIT: &str = r#"    Updating crates.io index
+   Packaging foo v0.1.0 (/ws/foo)
+   Uploading foo v0.1.0 (/ws/foo)
+error: failed to publish to registry at https://crates.io
+
+Caused by:
+  the remote server responded with an error (status 429 Too Many Requests): You have published too many new crates in a short period of time. Please try again after Mon, 01 Jan 2024 00:00:00 GMT or email help@crates.io to have your limit increased.
+"#;
+
+    const COMPILE: &str = r#"    Updating crates.io index
+   Packaging foo v0.1.0 (/ws/foo)
+   Verifying foo v0.1.0 (/ws/foo)
+   Compiling foo v0.1.0 (/ws/target/package/foo-0.1.0)
+error[E0425]: cannot find value `x` in this scope
+ --> src/lib.rs:2:5
+  |
+2 |     x
+  |     ^ not found in this scope
+
+For more information about this error, try `rustc --explain E0425`.
+error: could not compile `foo` (lib) due to 1 previous error
+error: failed to verify package tarball
+"#;
+
+    /// A compile error that happens to say "too many", which isn't rate limiting.
+    const COMPILE_TOO_MANY: &str = r#"   Verifying foo v0.1.0 (/ws/foo)
+   Compiling foo v0.1.0 (/ws/target/package/foo-0.1.0)
+error: too many `#` symbols: raw strings may be delimited by up to 255 `#` symbols, but found 256
+ --> src/lib.rs:1:13
+
+error: could not compile `foo` (lib) due to 1 previous error
+error: failed to verify package tarball
+"#;
+
+    const UPLOADED: &str = r#"    Updating crates.io index
+   Packaging foo v0.1.0 (/ws/foo)
+   Uploading foo v0.1.0 (/ws/foo)
+error: failed to publish to registry at https://crates.io
+
+Caused by:
+  the remote server responded with an error (status 400 Bad Request): crate version `0.1.0` is already uploaded
+"#;
+
+    const EXISTS: &str = r#"    Updating crates.io index
+error: crate foo@0.1.0 already exists on crates.io index
+"#;
+
+    const INDEX_LAG: &str = r#"    Updating crates.io index
+   Packaging foo v0.1.0 (/ws/foo)
+error: failed to prepare local package for uploading
+
+Caused by:
+  no matching package named `bar` found
+  location searched: crates.io index
+  required by package `foo v0.1.0 (/ws/foo)`
+"#;
+
+    const INDEX_LAG_VERSION: &str = r#"    Updating crates.io index
+   Packaging foo v0.1.0 (/ws/foo)
+error: failed to prepare local package for uploading
+
+Caused by:
+  failed to select a version for the requirement `bar = "^0.2.0"`
+  candidate versions found which didn't match: 0.1.0
+  location searched: crates.io index
+  required by package `foo v0.1.0 (/ws/foo)`
+"#;
+
+    fn classify(output: &str) -> PublishError {
+        let published = BTreeSet::from(["bar".to_string()]);
+        PublishError::classify(output.to_string(), &published)
+    }
+
+    #[test]
+    fn classify_cargo_errors() {
+        assert!(matches!(classify(AUTH), PublishError::Auth(_)));
+        assert!(matches!(classify(NO_TOKEN), PublishError::Auth(_)));
+        assert!(matches!(classify(RATE_LIMIT), PublishError::RateLimit(_)));
+        assert!(matches!(classify(COMPILE), PublishError::Build(_)));
+        assert!(matches!(classify(COMPILE_TOO_MANY), PublishError::Build(_)));
+        assert!(matches!(
+            classify(UPLOADED),
+            PublishError::AlreadyPublished(_)
+        ));
+        assert!(matches!(
+            classify(EXISTS),
+            PublishError::AlreadyPublished(_)
+        ));
+        assert!(matches!(
+            classify(INDEX_LAG),
+            PublishError::IndexLag { dep, .. } if dep == "bar"
+        ));
+        assert!(matches!(
+            classify(INDEX_LAG_VERSION),
+            PublishError::IndexLag { dep, .. } if dep == "bar"
+        ));
+    }
+
+    #[test]
+    fn already_uploaded_is_recognised() {
+        assert!(already_published(UPLOADED));
+        assert!(already_published(EXISTS));
+        assert!(already_published(
+            "error: failed to publish to registry at https://crates.io\n\nCaused by:\n  the remote \
+             server responded with an error (status 400 Bad Request): crate `foo@0.1.0` already \
+             exists"
+        ));
+
+        for output in [AUTH, RATE_LIMIT, COMPILE, INDEX_LAG] {
+            assert!(!already_published(output));
+        }
+        assert!(!already_published(
+            "error: failed to publish foo\n\nCaused by:\n  failed to create directory \
+             `/ws/target/package`: a file named `package` already exists"
+        ));
+        assert!(!already_published(
+            "error: failed to verify package tarball\n\nCaused by:\n  destination \
+             `/ws/target/package/foo-0.1.0` already exists on disk"
+        ));
+    }
+
+    #[test]
+    fn missing_dep_not_published_this_run() {
+        let output = INDEX_LAG.replace("`bar`", "`baz`");
+        assert!(matches!(classify(&output), PublishError::Other(_)));
+    }
+
+    #[test]
+    fn display_shows_cargos_error() {
+        assert_eq!(
+            classify(COMPILE).to_string(),
+            "failed to build: could not compile `foo` (lib) due to 1 previous error"
+        );
+    }
+
+    #[test]
+    fn failed_rewrite_restores_files() {
+        // serde is neither planned nor a member so bumping the workspace dependency fails after
+        // the members have been rewritten
+        let dir = write_tree(&[
+            (
+                "Cargo.toml",
+                "[workspace]\nmembers = [\"a\", \"b\"]\n\n[workspace.dependencies]\nserde = { version = \"1.0\" }\n",
+            ),
+            (
+                "a/Cargo.toml",
+                "[package]\nname = \"a\"\nversion = \"0.1.0\"\n\n[dependencies]\nb = { path = \"../b\", version = \"0.1.0\" }\nserde = { workspace = true }\n",
+            ),
+            ("a/src/lib.rs", ""),
+            ("b/Cargo.toml", "[package]\nname = \"b\"\nversion = \"0.1.0\"\n"),
+            ("b/src/lib.rs", ""),
+        ]);
+        let gctx = gctx();
+        let workspace = Workspace::new(&dir.path().join("Cargo.toml"), &gctx).unwrap();
+        let before = shared::snapshot_files(&workspace).unwrap();
+        let plan = Planner {
+            options: Options {
+                description: Some("released".to_string()),
+                ..Default::default()
+            },
+            crates: [("a", vec!["b", "serde"]), ("b", vec![])]
+                .map(|(name, deps)| Publish {
+                    name: name.to_string(),
+                    from: "0.1.0".to_string(),
+                    to: "0.2.0".to_string(),
+                    publish: true,
+                    verify: true,
+                    rewrite_dep: deps
+                        .into_iter()
+                        .map(|dep| RewriteDep {
+                            name: dep.to_string(),
+                            ..Default::default()
+                        })
+                        .collect(),
+                    ..Default::default()
+                })
+                .to_vec(),
+            ..Default::default()
+        };
+
+        let err = rewrite_or_restore(
+            &Apply::parse_from(["apply"]),
+            &workspace,
+            workspace.root(),
+            &Default::default(),
+            &plan,
+            &BTreeMap::new(),
+            &Profile::new(),
+        )
+        .unwrap_err();
+        assert!(format!("{:#}", err).contains("cant find package serde"));
+
+        assert_eq!(shared::snapshot_files(&workspace).unwrap(), before);
+        assert!(!dir.path().join("a/README.md").exists());
+    }
+}
@@ -1,17 +1,20 @@
-use std::collections::HashSet;
-use std::env::current_dir;
+use std::collections::{BTreeMap, HashSet, VecDeque};
+use std::env;
 use std::fmt::Display;
 use std::io::Write;
 use std::path::{Path, PathBuf};
 use std::process::Command;
 use std::str::FromStr;
 
-use crate::cli::{Args, Changed};
-use crate::plan::BumpKind;
-use anyhow::{bail, Result};
+use crate::cli::{Args, Changed, Format};
+use crate::plan::{self, BumpKind};
+use crate::registry;
+use crate::shared::{self, git};
+use anyhow::{bail, Context, Result};
 use cargo::core::dependency::DepKind;
 use cargo::core::Workspace;
-use termcolor::{ColorSpec, WriteColor};
+use cargo::sources::IndexSummary;
+use termcolor::{Color, ColorSpec, WriteColor};
 use toml_edit::visit_mut::VisitMut;
 use toml_edit::Table;
 
@@ -35,7 +38,7 @@ impl VisitMut for Sorter {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, serde::Serialize)]
 pub struct Change {
     pub name: String,
     pub path: PathBuf,
@@ -43,7 +46,8 @@ pub struct Change {
     pub bump: BumpKind,
 }
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
 pub enum ChangeKind {
     Files,
     Manifest,
@@ -64,17 +68,42 @@ pub async fn handle_changed(args: Args, diff: Changed) -> Result<()> {
     let mut stdout = args.stdout();
     let config = cargo::GlobalContext::default()?;
     config.shell().set_verbosity(cargo::core::Verbosity::Quiet);
-    let path = current_dir()?.join("Cargo.toml");
+    let path = args.manifest_path()?;
     let workspace = Workspace::new(&path, &config)?;
     let deps = !diff.no_deps && !diff.manifests;
 
-    let crates = get_changed_crates(&workspace, deps, &diff.from, &diff.to)?;
-
-    for c in crates {
-        if diff.manifests && c.kind != ChangeKind::Manifest {
-            continue;
+    let mut crates = match &diff.from {
+        Some(from) => {
+            let from = resolve_base(workspace.root(), from)?;
+            get_changed_crates(&workspace, false, &from, &diff.to)?
         }
+        None => changed_since_release(&args, &workspace, &diff).await?,
+    };
+    let direct = crates
+        .iter()
+        .filter(|c| c.bump == BumpKind::Major)
+        .map(|c| c.name.clone())
+        .collect::<HashSet<_>>();
+    if deps {
+        find_indirect_changes(&workspace, &mut crates);
+    }
+
+    let members = shared::members(&args, &workspace)
+        .map(|c| c.name().as_str())
+        .collect::<HashSet<_>>();
+    crates.retain(|c| members.contains(c.name.as_str()));
+
+    if diff.manifests {
+        crates.retain(|c| c.kind == ChangeKind::Manifest);
+    }
+
+    if diff.format == Format::Json {
+        serde_json::to_writer_pretty(&mut stdout, &crates)?;
+        writeln!(stdout)?;
+        return Ok(());
+    }
 
+    for c in crates {
         if diff.paths >= 2 {
             writeln!(stdout, "{}", c.path.join("Cargo.toml").display())?;
         } else if diff.paths == 1 {
@@ -87,6 +116,11 @@ pub async fn handle_changed(args: Args, diff: Changed) -> Result<()> {
             stdout.set_color(ColorSpec::new().set_bold(false))?;
             writeln!(stdout, " ({}):", c.path.display())?;
             writeln!(stdout, "    {}", c.kind)?;
+            if diff.explain && !direct.contains(&c.name) {
+                if let Some(path) = change_path(&workspace, &direct, &c.name) {
+                    writeln!(stdout, "    because of {}", path.join(" -> "))?;
+                }
+            }
             writeln!(stdout)?;
         }
     }
@@ -94,7 +128,140 @@ pub async fn handle_changed(args: Args, diff: Changed) -> Result<()> {
     Ok(())
 }
 
+/// Finds the direct changes to each crate since the git tag of its latest release.
+async fn changed_since_release(
+    args: &Args,
+    w: &Workspace<'_>,
+    diff: &Changed,
+) -> Result<Vec<Change>> {
+    let upstream = plan::get_upstream(args, w).await?;
+    let commits = release_commits(args, w, &upstream, &diff.from_tag_pattern)?;
+    let to = diff.release_to.as_deref().unwrap_or("HEAD");
+
+    // crates released at the same commit can be diffed together
+    let mut changed = Vec::new();
+    for (commit, names) in commits {
+        let mut changes = get_changed_crates(w, false, &commit, to)?;
+        changes.retain(|c| names.contains(&c.name));
+        changed.extend(changes);
+    }
+
+    let order = w.members().map(|c| c.name().as_str()).collect::<Vec<_>>();
+    changed.sort_by_key(|c| order.iter().position(|n| *n == c.name));
+    Ok(changed)
+}
+
+/// Finds the commit each crate's latest release is tagged at, grouping crates released at the
+/// same commit. Crates with no matching tag are warned about and left out.
+pub fn release_commits(
+    args: &Args,
+    w: &Workspace,
+    upstream: &BTreeMap<String, Vec<IndexSummary>>,
+    patterns: &[String],
+) -> Result<BTreeMap<String, HashSet<String>>> {
+    let mut stderr = args.stderr();
+    let mut commits = BTreeMap::<String, HashSet<String>>::new();
+    let mut missing = Vec::new();
+
+    for c in w.members().filter(|c| c.publish().is_none()) {
+        let name = c.name().as_str();
+        let Some(version) = upstream
+            .get(name)
+            .and_then(|u| registry::latest_release(u, false))
+            .map(|u| u.as_summary().version().to_string())
+        else {
+            missing.push(format!("{} (never released)", name));
+            continue;
+        };
+
+        let commit = patterns.iter().find_map(|pattern| {
+            let tag = pattern
+                .replace("{crate}", name)
+                .replace("{version}", &version);
+            rev_parse(w.root(), &format!("refs/tags/{}^{{commit}}", tag)).ok()
+        });
+
+        match commit {
+            Some(commit) => {
+                commits.entry(commit).or_default().insert(name.to_string());
+            }
+            None => missing.push(format!("{}@{}", name, version)),
+        }
+    }
+
+    if !missing.is_empty() {
+        stderr.set_color(ColorSpec::new().set_fg(Some(Color::Yellow)).set_bold(true))?;
+        write!(stderr, "warning: ")?;
+        stderr.set_color(&ColorSpec::new())?;
+        writeln!(
+            stderr,
+            "no release tag found for {} packages:",
+            missing.len()
+        )?;
+        for c in missing {
+            writeln!(stderr, "    {}", c)?;
+        }
+    }
+
+    Ok(commits)
+}
+
+/// Finds the shortest dependency path from `name` to a crate in `direct`, the way
+/// [`find_indirect_changes`] would have marked it as changed.
+fn change_path<'a>(
+    w: &'a Workspace,
+    direct: &HashSet<String>,
+    start: &'a str,
+) -> Option<Vec<&'a str>> {
+    let mut parent = BTreeMap::new();
+    let mut queue = VecDeque::from([start]);
+
+    while let Some(name) = queue.pop_front() {
+        let c = w.members().find(|c| c.name().as_str() == name)?;
+
+        for dep in c
+            .dependencies()
+            .iter()
+            .filter(|d| d.kind() != DepKind::Development)
+        {
+            let dep = dep.package_name().as_str();
+            let Some(dep_crate) = w.members().find(|c| c.name().as_str() == dep) else {
+                continue;
+            };
+            if dep == start || dep_crate.publish().is_some() || parent.contains_key(dep) {
+                continue;
+            }
+            parent.insert(dep, name);
+
+            if direct.contains(dep) {
+                let mut path = vec![dep];
+                while let Some(&p) = parent.get(path[path.len() - 1]) {
+                    path.push(p);
+                }
+                path.reverse();
+                return Some(path);
+            }
+            queue.push_back(dep);
+        }
+    }
+
+    None
+}
+
 pub fn find_indirect_changes(w: &Workspace, changed: &mut Vec<Change>) {
+    propagate_changes(w, changed, BumpKind::Major, BumpKind::Major);
+}
+
+/// Like [`find_indirect_changes`] but crates that depend on a crate with any change are also given
+/// at least a patch bump, so every dependent is released again.
+pub fn find_all_indirect_changes(w: &Workspace, changed: &mut Vec<Change>) {
+    find_indirect_changes(w, changed);
+    propagate_changes(w, changed, BumpKind::Patch, BumpKind::Patch);
+}
+
+/// Gives every crate that depends, directly or not, on a crate changed by at least `min` at
+/// least `bump`.
+fn propagate_changes(w: &Workspace, changed: &mut Vec<Change>, min: BumpKind, bump: BumpKind) {
     let mut dependants = HashSet::new();
 
     for c in w.members() {
@@ -109,7 +276,7 @@ pub fn find_indirect_changes(w: &Workspace, changed: &mut Vec<Change>) {
         {
             if changed
                 .iter()
-                .filter(|ch| ch.bump == BumpKind::Major)
+                .filter(|ch| ch.bump >= min)
                 .any(|ch| ch.name == dep.package_name().as_str())
             {
                 dependants.insert(c.name().as_str());
@@ -143,7 +310,7 @@ pub fn find_indirect_changes(w: &Workspace, changed: &mut Vec<Change>) {
 
     for c in dependants {
         if let Some(change) = changed.iter_mut().find(|ch| ch.name == c) {
-            change.bump = BumpKind::Major;
+            change.bump = change.bump.max(bump);
         } else {
             let path = w
                 .members()
@@ -156,7 +323,7 @@ pub fn find_indirect_changes(w: &Workspace, changed: &mut Vec<Change>) {
                 name: c.to_string(),
                 path: path.to_path_buf(),
                 kind: ChangeKind::Dependency,
-                bump: BumpKind::Major,
+                bump,
             };
             changed.push(change);
         }
@@ -184,15 +351,18 @@ pub fn get_changed_crates(w: &Workspace, deps: bool, from: &str, to: &str) -> Re
 
         src_files.retain(|f| changed_files.contains(f));
 
-        if src_files.len() == 1
-            && src_files[0].ends_with("/Cargo.toml")
-            && manifest_changed(w.root(), &src_files[0], from, to)? != BumpKind::None
-        {
+        let manifest_bump = if src_files.len() == 1 && src_files[0].ends_with("/Cargo.toml") {
+            manifest_changed(w.root(), &src_files[0], from, to)?
+        } else {
+            BumpKind::None
+        };
+
+        if manifest_bump != BumpKind::None {
             let change = Change {
                 name: c.name().to_string(),
                 path: path.to_path_buf(),
                 kind: ChangeKind::Manifest,
-                bump: BumpKind::Major,
+                bump: manifest_bump,
             };
             changed.push(change);
         } else if !src_files.is_empty() {
@@ -246,23 +416,55 @@ pub fn manifest_changed(root: &Path, path: &str, from: &str, to: &str) -> Result
         Sorter.visit_document_mut(c);
     }
 
+    let features_bump = features_changed(&old, &new);
+
+    for c in [&mut old, &mut new] {
+        c.remove("features");
+    }
+
     let changed = old.to_string() != new.to_string();
     if changed {
-        Ok(BumpKind::Minor)
+        Ok(features_bump.max(BumpKind::Minor))
+    } else {
+        Ok(features_bump)
+    }
+}
+
+/// Removing a feature breaks crates that enable it. Adding or changing one does not.
+fn features_changed(old: &toml_edit::DocumentMut, new: &toml_edit::DocumentMut) -> BumpKind {
+    let features = |doc: &toml_edit::DocumentMut| {
+        doc.get("features")
+            .and_then(|f| f.as_table_like())
+            .map(|f| {
+                f.iter()
+                    .map(|(k, v)| (k.to_string(), v.to_string()))
+                    .collect::<BTreeMap<_, _>>()
+            })
+            .unwrap_or_default()
+    };
+    let old = features(old);
+    let new = features(new);
+
+    if old.keys().any(|k| !new.contains_key(k)) {
+        BumpKind::Major
+    } else if old != new {
+        BumpKind::Minor
     } else {
-        Ok(BumpKind::None)
+        BumpKind::None
     }
 }
 
 fn get_file(root: &Path, path: &str, r: &str) -> Result<String> {
     let file = format!("{}:{}", r, path);
 
-    let res = Command::new("git")
-        .arg("-C")
-        .arg(root)
-        .arg("show")
-        .arg(file)
-        .output()?;
+    let res = git(
+        Command::new("git")
+            .arg("-C")
+            .arg(root)
+            .arg("show")
+            .arg(file),
+        "show",
+    )?;
 
     if !res.status.success() {
         bail!("git exited non 0-");
@@ -271,17 +473,141 @@ fn get_file(root: &Path, path: &str, r: &str) -> Result<String> {
     Ok(String::from_utf8(res.stdout)?)
 }
 
+pub fn head_commit(root: &Path) -> Result<String> {
+    rev_parse(root, "HEAD")
+}
+
+/// The value of `--since` and friends that stands for the merge-base with the base branch.
+pub const BASE: &str = "@base";
+
+/// Resolves [`BASE`] to the merge-base of HEAD and the base branch, other revs are returned as is.
+///
+/// The base branch is `PARITY_PUBLISH_BASE_BRANCH` if set, otherwise the branch `origin/HEAD`
+/// points to, falling back to `origin/main`, `origin/master`, `main` and `master`.
+pub fn resolve_base(root: &Path, rev: &str) -> Result<String> {
+    if rev != BASE {
+        return Ok(rev.to_string());
+    }
+
+    let branch = match env::var("PARITY_PUBLISH_BASE_BRANCH") {
+        Ok(branch) => branch,
+        Err(_) => default_branch(root).context(
+            "can't find the base branch for @base, set PARITY_PUBLISH_BASE_BRANCH to choose one",
+        )?,
+    };
+
+    let res = git(
+        Command::new("git")
+            .arg("-C")
+            .arg(root)
+            .arg("merge-base")
+            .arg("HEAD")
+            .arg(&branch),
+        "merge-base",
+    )?;
+
+    if !res.status.success() {
+        bail!(
+            "no merge-base between HEAD and {}, fetch {} (shallow clones need enough history) or \
+             set PARITY_PUBLISH_BASE_BRANCH\n{}",
+            branch,
+            branch,
+            String::from_utf8_lossy(&res.stderr).trim()
+        );
+    }
+
+    Ok(String::from_utf8(res.stdout)?.trim().to_string())
+}
+
+fn default_branch(root: &Path) -> Option<String> {
+    let res = git(
+        Command::new("git")
+            .arg("-C")
+            .arg(root)
+            .arg("symbolic-ref")
+            .arg("--short")
+            .arg("refs/remotes/origin/HEAD"),
+        "symbolic-ref",
+    )
+    .ok()?;
+
+    if res.status.success() {
+        return Some(String::from_utf8_lossy(&res.stdout).trim().to_string());
+    }
+
+    ["origin/main", "origin/master", "main", "master"]
+        .into_iter()
+        .find(|b| rev_parse(root, b).is_ok())
+        .map(|b| b.to_string())
+}
+
+pub fn rev_parse(root: &Path, rev: &str) -> Result<String> {
+    let res = git(
+        Command::new("git")
+            .arg("-C")
+            .arg(root)
+            .arg("rev-parse")
+            .arg(rev),
+        "rev-parse",
+    )?;
+
+    if !res.status.success() {
+        bail!("{}", String::from_utf8_lossy(&res.stderr));
+    }
+
+    Ok(String::from_utf8(res.stdout)?.trim().to_string())
+}
+
+/// Lists files with uncommitted changes, including untracked files, as absolute paths.
+pub fn dirty_files(root: &Path) -> Result<Vec<PathBuf>> {
+    let toplevel = PathBuf::from(rev_parse(root, "--show-toplevel")?);
+
+    let res = git(
+        Command::new("git")
+            .arg("-C")
+            .arg(root)
+            .arg("status")
+            .arg("--porcelain")
+            .arg("-z")
+            .arg("--untracked-files=all"),
+        "status",
+    )?;
+
+    if !res.status.success() {
+        bail!("{}", String::from_utf8_lossy(&res.stderr));
+    }
+
+    let out = String::from_utf8(res.stdout)?;
+    let mut entries = out.split('\0').filter(|e| !e.is_empty());
+    let mut files = Vec::new();
+
+    while let Some(entry) = entries.next() {
+        let Some(file) = entry.get(3..) else {
+            continue;
+        };
+        // renames and copies are followed by the original path
+        if entry.starts_with(['R', 'C']) {
+            entries.next();
+        }
+        files.push(toplevel.join(file));
+    }
+
+    Ok(files)
+}
+
 fn get_changed_files(w: &Workspace, from: &str, to: &str) -> Result<HashSet<String>> {
     let root = w.root();
 
-    let res = Command::new("git")
-        .arg("-C")
-        .arg(root)
-        .arg("diff")
-        .arg("--name-only")
-        .arg(from)
-        .arg(to)
-        .output()?;
+    let res = git(
+        Command::new("git")
+            .arg("-C")
+            .arg(root)
+            .arg("diff")
+            .arg("--name-only")
+            .arg(from)
+            .arg(to),
+        "diff",
+    )?;
 
     if !res.status.success() {
         bail!("{}", String::from_utf8_lossy(&res.stderr));
@@ -315,3 +641,48 @@ pub fn get_crate_hash(c: &Package, r: &str) -> Result<String> {
     Ok(hash)
 }
 */
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shared::test::{commit_all, write_tree};
+
+    const MANIFEST: &str = r#"[package]
+name = "a"
+version = "0.1.0"
+
+[features]
+default = ["std"]
+std = []
+"#;
+
+    /// The bump for changing `a`'s manifest from [`MANIFEST`] to `new`.
+    fn manifest_bump(new: &str) -> BumpKind {
+        let dir = write_tree(&[("a/Cargo.toml", MANIFEST)]);
+        let from = commit_all(dir.path());
+        std::fs::write(dir.path().join("a/Cargo.toml"), new).unwrap();
+        let to = commit_all(dir.path());
+        manifest_changed(dir.path(), "a/Cargo.toml", &from, &to).unwrap()
+    }
+
+    #[test]
+    fn added_feature_is_minor() {
+        let new = format!("{}serde = []\n", MANIFEST);
+        assert_eq!(manifest_bump(&new), BumpKind::Minor);
+    }
+
+    #[test]
+    fn removed_feature_is_major() {
+        let new = MANIFEST.replace("default = [\"std\"]\nstd = []\n", "default = []\n");
+        assert_eq!(manifest_bump(&new), BumpKind::Major);
+    }
+
+    #[test]
+    fn version_and_reordered_features_are_unchanged() {
+        let new = MANIFEST.replace("0.1.0", "0.2.0").replace(
+            "default = [\"std\"]\nstd = []\n",
+            "std = []\ndefault = [\"std\"]\n",
+        );
+        assert_eq!(manifest_bump(&new), BumpKind::None);
+    }
+}
@@ -1,23 +1,27 @@
 use crate::{
-    cli::{Args, Check},
-    shared::{cratesio, get_owners, Owner},
+    cli::{Args, Check, IssueKind},
+    registry,
+    shared::{cratesio, get_owners, members, workspace_crates, Cache, Owner},
 };
 
 use std::{
     collections::{BTreeMap, BTreeSet},
-    env::current_dir,
+    fs::read_to_string,
     io::Write,
-    path::PathBuf,
+    path::{Path, PathBuf},
     process::exit,
     sync::Arc,
 };
 
 use anyhow::{Context, Result};
 use cargo::{
-    core::{dependency::DepKind, Workspace},
-    util::VersionExt,
+    core::{dependency::DepKind, Package, Workspace},
+    sources::IndexSummary,
+    util::{cache_lock::CacheLockMode, VersionExt},
 };
-use termcolor::{ColorChoice, ColorSpec, StandardStream, WriteColor};
+use clap::ValueEnum;
+use semver::Version;
+use termcolor::{Color, ColorChoice, ColorSpec, StandardStream, WriteColor};
 
 struct NamePath {
     name: String,
@@ -31,40 +35,63 @@ struct Issues {
     no_desc: bool,
     no_repo: bool,
     no_license: bool,
+    invalid_license: Option<String>,
     unpublished: bool,
     taken: bool,
     broken_readme: bool,
+    missing_targets: Vec<PathBuf>,
     prerelease: bool,
     version_zero: bool,
+    excluded_files: Vec<PathBuf>,
+    empty_inherited: Vec<String>,
+    foreign_deps: Vec<String>,
+    conflicting_deps: Vec<String>,
+    no_rust_version: bool,
+    rust_version_mismatch: Option<String>,
+    version_ahead: Option<Version>,
     needs_publish: Option<Vec<NamePath>>,
 }
 
 impl Issues {
     fn has_issue(&self) -> bool {
-        self.no_license
-            || self.taken
-            || self.broken_readme
-            || self.needs_publish.is_some()
-            || self.no_desc
-            || self.no_repo
-            || self.unpublished
-            || self.prerelease
-            || self.version_zero
+        !self.kinds().is_empty()
+    }
+
+    fn kinds(&self) -> Vec<IssueKind> {
+        [
+            (self.no_desc, IssueKind::NoDesc),
+            (self.no_repo, IssueKind::NoRepo),
+            (self.no_license, IssueKind::NoLicense),
+            (self.invalid_license.is_some(), IssueKind::InvalidLicense),
+            (self.unpublished, IssueKind::Unpublished),
+            (self.taken, IssueKind::Taken),
+            (self.broken_readme, IssueKind::BrokenReadme),
+            (!self.missing_targets.is_empty(), IssueKind::MissingTarget),
+            (self.version_zero, IssueKind::VersionZero),
+            (self.prerelease, IssueKind::Prerelease),
+            (!self.excluded_files.is_empty(), IssueKind::ExcludedFiles),
+            (!self.empty_inherited.is_empty(), IssueKind::EmptyInherited),
+            (!self.foreign_deps.is_empty(), IssueKind::ForeignDeps),
+            (
+                !self.conflicting_deps.is_empty(),
+                IssueKind::ConflictingDeps,
+            ),
+            (self.no_rust_version, IssueKind::NoRustVersion),
+            (
+                self.rust_version_mismatch.is_some(),
+                IssueKind::RustVersionMismatch,
+            ),
+            (self.version_ahead.is_some(), IssueKind::VersionAhead),
+            (self.needs_publish.is_some(), IssueKind::NeedsPublish),
+        ]
+        .into_iter()
+        .filter(|(has, _)| *has)
+        .map(|(_, kind)| kind)
+        .collect()
     }
 
     fn ret_err(&self, check: &Check) -> bool {
-        let no_desc = self.no_desc && !check.allow_nonfatal;
-        let no_repo = self.no_repo && !check.allow_nonfatal;
-        let unpublished = self.no_desc && !check.allow_unpublished;
-        self.no_license
-            || self.taken
-            || self.broken_readme
-            || self.needs_publish.is_some()
-            || self.prerelease
-            || self.version_zero
-            || no_desc
-            || no_repo
-            || unpublished
+        self.kinds().into_iter().any(|kind| fails(kind, check))
     }
 
     fn print(&self, check: &Check, stdout: &mut StandardStream) -> Result<()> {
@@ -93,6 +120,13 @@ impl Issues {
             if self.no_license {
                 writeln!(stdout, "    no license")?;
             }
+            if let Some(err) = &self.invalid_license {
+                writeln!(
+                    stdout,
+                    "    license is not a valid SPDX expression: {}",
+                    err
+                )?;
+            }
             if self.unpublished {
                 writeln!(stdout, "    unpublished on crates.io")?;
             }
@@ -102,12 +136,52 @@ impl Issues {
             if self.broken_readme {
                 writeln!(stdout, "    readme specified in Cargo.toml doesnt exist")?;
             }
+            for target in &self.missing_targets {
+                writeln!(
+                    stdout,
+                    "    target {} specified in Cargo.toml doesnt exist",
+                    target.display()
+                )?;
+            }
             if self.version_zero {
                 writeln!(stdout, "    version is 0.0.0. Should be at least 0.1.0")?;
             }
             if self.prerelease {
                 writeln!(stdout, "    version should not be prerelease")?;
             }
+            for file in &self.excluded_files {
+                writeln!(
+                    stdout,
+                    "    {} is specified in Cargo.toml but is not included in the package",
+                    file.display()
+                )?;
+            }
+            for field in &self.empty_inherited {
+                writeln!(
+                    stdout,
+                    "    {} is inherited from the workspace but [workspace.package] doesn't set it",
+                    field
+                )?;
+            }
+            for dep in &self.foreign_deps {
+                writeln!(stdout, "    {}", dep)?;
+            }
+            for dep in &self.conflicting_deps {
+                writeln!(stdout, "    {}", dep)?;
+            }
+            if self.no_rust_version {
+                writeln!(stdout, "    no rust-version")?;
+            }
+            if let Some(msrv) = &self.rust_version_mismatch {
+                writeln!(stdout, "    {}", msrv)?;
+            }
+            if let Some(published) = &self.version_ahead {
+                writeln!(
+                    stdout,
+                    "    version is more than one major release ahead of {} on crates.io",
+                    published
+                )?;
+            }
             if let Some(ref deps) = self.needs_publish {
                 writeln!(
                     stdout,
@@ -129,17 +203,153 @@ impl Issues {
     }
 }
 
+/// The published versions of the members, for `version-ahead`.
+///
+/// That's only a warning so a registry that can't be reached leaves it out rather than failing
+/// the check.
+fn published_versions(
+    args: &Args,
+    workspace: &Workspace,
+) -> Result<BTreeMap<String, Vec<IndexSummary>>> {
+    let mut upstream = BTreeMap::new();
+    let mut lookup = || -> Result<()> {
+        let _lock = workspace
+            .gctx()
+            .acquire_package_cache_lock(CacheLockMode::DownloadExclusive)?;
+        let mut reg = registry::get_registry(workspace)?;
+        let crates = members(args, workspace)
+            .filter(|c| c.publish().is_none())
+            .collect::<Vec<_>>();
+        registry::download_packages(&mut reg, crates.iter().copied())?;
+        for c in crates {
+            match registry::get_crate(&mut reg, c.name()) {
+                Ok(versions) => {
+                    upstream.insert(c.name().to_string(), versions);
+                }
+                Err(e) if registry::is_not_found(&e) => (),
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    };
+
+    if let Err(e) = lookup() {
+        let mut stderr = args.stderr();
+        stderr.set_color(ColorSpec::new().set_fg(Some(Color::Yellow)).set_bold(true))?;
+        write!(stderr, "warning: ")?;
+        stderr.set_color(&ColorSpec::new())?;
+        writeln!(
+            stderr,
+            "failed to look up published versions, not checking version-ahead: {:#}",
+            e
+        )?;
+        upstream.clear();
+    }
+
+    Ok(upstream)
+}
+
+/// Whether `local` is more than one breaking release ahead of `published`, which usually means
+/// versions were bumped without publishing.
+fn version_ahead(local: &Version, published: &Version) -> bool {
+    if local.major > 0 {
+        local.major > published.major + 1
+    } else {
+        published.major == 0 && local.minor > published.minor + 1
+    }
+}
+
+/// Why `license` would be rejected by crates.io, which parses it as an SPDX expression but still
+/// accepts `/` for `OR` and a few other legacy forms.
+fn invalid_license(license: &str) -> Option<String> {
+    let mode = spdx::ParseMode {
+        allow_slash_as_or_operator: true,
+        allow_imprecise_license_names: false,
+        allow_postfix_plus_on_gpl: true,
+        allow_deprecated: true,
+        allow_unknown: false,
+    };
+    spdx::Expression::parse_mode(license, mode)
+        .err()
+        .map(|e| format!("{} '{}'", e.reason, &e.original[e.span]))
+}
+
+/// Whether an issue makes check exit 1 when it isn't passed to `--allow` or `--deny`.
+fn is_fatal(kind: IssueKind, check: &Check) -> bool {
+    match kind {
+        IssueKind::NoDesc | IssueKind::NoRepo | IssueKind::ForeignDeps => !check.allow_nonfatal,
+        // only reported unless denied, plenty of crates don't set one
+        IssueKind::NoRustVersion | IssueKind::RustVersionMismatch => false,
+        // a warning, the version may have been bumped on purpose
+        IssueKind::VersionAhead => false,
+        // new crates are expected to be unpublished until their first release
+        IssueKind::Unpublished => false,
+        _ => true,
+    }
+}
+
+/// Whether an issue makes check exit 1 once `--allow` and `--deny` are taken into account.
+pub fn fails(kind: IssueKind, check: &Check) -> bool {
+    check.deny.contains(&kind) || (!check.allow.contains(&kind) && is_fatal(kind, check))
+}
+
+fn print_summary(issues: &[Issues], check: &Check, stdout: &mut StandardStream) -> Result<()> {
+    let with_issues = issues.iter().filter(|i| i.has_issue()).count();
+    let fail = issues.iter().any(|i| i.ret_err(check));
+
+    if with_issues == 0 {
+        writeln!(stdout, "no issues found")?;
+        return Ok(());
+    }
+
+    writeln!(stdout, "summary:")?;
+    for &kind in IssueKind::value_variants() {
+        let n = issues.iter().filter(|i| i.kinds().contains(&kind)).count();
+        if n == 0 {
+            continue;
+        }
+        let name = kind.to_possible_value().unwrap();
+        let severity = if fails(kind, check) { "" } else { " (allowed)" };
+        writeln!(stdout, "    {:<24}{:>5}{}", name.get_name(), n, severity)?;
+    }
+
+    stdout.set_color(ColorSpec::new().set_bold(true))?;
+    write!(stdout, "{} crates with issues", with_issues)?;
+    stdout.set_color(&ColorSpec::new())?;
+    if fail {
+        stdout.set_color(ColorSpec::new().set_fg(Some(Color::Red)).set_bold(true))?;
+        writeln!(stdout, ", check failed")?;
+    } else {
+        stdout.set_color(ColorSpec::new().set_fg(Some(Color::Green)).set_bold(true))?;
+        writeln!(stdout, ", check passed")?;
+    }
+    stdout.set_color(&ColorSpec::new())?;
+
+    Ok(())
+}
+
 pub async fn handle_check(args: Args, chk: Check) -> Result<()> {
-    exit(check(&args, chk).await?)
+    exit(check(&args, chk, None).await?)
 }
 
-pub async fn check(args: &Args, check: Check) -> Result<i32> {
+/// Checks the workspace, returning the exit code.
+///
+/// `upstream` is the registry data plan already looked up, check looks up the members itself
+/// when it isn't given.
+pub async fn check(
+    args: &Args,
+    check: Check,
+    upstream: Option<&BTreeMap<String, Vec<IndexSummary>>>,
+) -> Result<i32> {
     let mut stdout = args.stdout();
-    let issues = issues(&check).await?;
+    let issues = issues(args, &check, upstream).await?;
 
     for issue in &issues {
         issue.print(&check, &mut stdout)?;
     }
+    if !check.quiet && check.paths == 0 {
+        print_summary(&issues, &check, &mut stdout)?;
+    }
 
     if issues.iter().any(|i| i.ret_err(&check)) {
         Ok(1)
@@ -148,29 +358,396 @@ pub async fn check(args: &Args, check: Check) -> Result<i32> {
     }
 }
 
-async fn issues(check: &Check) -> Result<Vec<Issues>> {
+async fn issues(
+    args: &Args,
+    check: &Check,
+    upstream: Option<&BTreeMap<String, Vec<IndexSummary>>>,
+) -> Result<Vec<Issues>> {
     let mut all_issues = Vec::new();
 
     let mut stderr = StandardStream::stderr(ColorChoice::Auto);
 
-    let path = current_dir()?.join("Cargo.toml");
+    let path = args.manifest_path()?;
     let config = cargo::GlobalContext::default()?;
     config.shell().set_verbosity(cargo::core::Verbosity::Quiet);
-    let workspace = Workspace::new(&path, &config)?;
+    let workspace = match Workspace::new(&path, &config) {
+        Ok(workspace) => workspace,
+        Err(e) => {
+            // cargo refuses to load a member that inherits a field [workspace.package] doesn't
+            // set, so report those from the manifests instead of failing with cargo's error
+            let issues = unloadable_inherited_fields(args, &path)?;
+            if issues.is_empty() {
+                return Err(e);
+            }
+            return Ok(issues);
+        }
+    };
+    workspace_crates(&workspace)?;
+
+    let owners = if check.offline {
+        stderr.set_color(ColorSpec::new().set_fg(Some(Color::Yellow)).set_bold(true))?;
+        write!(stderr, "warning: ")?;
+        stderr.set_color(&ColorSpec::new())?;
+        writeln!(
+            stderr,
+            "running offline, ownership and publication status on crates.io is not verified"
+        )?;
+        vec![Owner::Us; members(args, &workspace).count()]
+    } else if check.no_check_owner {
+        vec![Owner::Us; members(args, &workspace).count()]
+    } else {
+        args.progress("looking up crate data, this may take a while....")?;
+        get_owners(
+            args,
+            &workspace,
+            &Arc::new(cratesio()?),
+            &Cache::new(&check.cache),
+        )
+        .await
+    };
+
+    let looked_up;
+    let upstream = match upstream {
+        Some(upstream) => upstream,
+        None if check.offline => {
+            looked_up = BTreeMap::new();
+            &looked_up
+        }
+        None => {
+            looked_up = published_versions(args, &workspace)?;
+            &looked_up
+        }
+    };
 
-    writeln!(stderr, "looking up crate data, this may take a while....")?;
+    args.progress("checking crates....")?;
 
-    let owners = if check.no_check_owner {
-        vec![Owner::Us; workspace.members().count()]
-    } else {
-        get_owners(&workspace, &Arc::new(cratesio()?)).await
+    let should_publish = needs_publish(args, &workspace, check.recursive);
+    let root_manifest: toml::Table = toml::from_str(&read_to_string(workspace.root_manifest())?)?;
+
+    for (c, owner) in members(args, &workspace).zip(owners) {
+        let path = c.root().strip_prefix(workspace.root())?;
+
+        let mut issues = Issues {
+            name: c.name().to_string(),
+            path: path.to_path_buf(),
+            ..Issues::default()
+        };
+
+        if c.publish().is_none() {
+            match owner {
+                Owner::Us => (),
+                Owner::None => issues.unpublished = true,
+                Owner::Other => issues.taken = true,
+            }
+
+            issues.no_desc = c.manifest().metadata().description.is_none();
+            issues.no_repo = c.manifest().metadata().repository.is_none();
+            issues.no_license = c.manifest().metadata().license.is_none()
+                && c.manifest().metadata().license_file.is_none();
+            issues.invalid_license = c
+                .manifest()
+                .metadata()
+                .license
+                .as_deref()
+                .and_then(invalid_license);
+
+            if let Some(readme) = &c.manifest().metadata().readme {
+                if !c
+                    .manifest_path()
+                    .parent()
+                    .context("no parent")?
+                    .join(readme)
+                    .exists()
+                {
+                    issues.broken_readme = true;
+                }
+            }
+
+            for target in c.targets().iter().filter(|t| t.is_lib() || t.is_bin()) {
+                if let Some(path) = target.src_path().path() {
+                    if !path.exists() {
+                        let path = path.strip_prefix(c.root()).unwrap_or(path);
+                        issues.missing_targets.push(path.to_path_buf());
+                    }
+                }
+            }
+
+            let manifest = c.manifest();
+            if !manifest.include().is_empty() || !manifest.exclude().is_empty() {
+                let mut src = cargo::sources::PathSource::new(
+                    c.root(),
+                    c.package_id().source_id(),
+                    workspace.gctx(),
+                );
+                src.load()?;
+                let src_files = src
+                    .list_files(c)?
+                    .into_iter()
+                    .filter_map(|f| f.canonicalize().ok())
+                    .collect::<BTreeSet<_>>();
+
+                let root = c.root().canonicalize()?;
+                let metadata = manifest.metadata();
+                for file in [&metadata.readme, &metadata.license_file]
+                    .into_iter()
+                    .flatten()
+                {
+                    // Files outside of the crate are copied in by cargo when packaging
+                    let Ok(path) = c.root().join(file).canonicalize() else {
+                        continue;
+                    };
+                    if path.starts_with(&root) && !src_files.contains(&path) {
+                        issues.excluded_files.push(file.into());
+                    }
+                }
+            }
+
+            if c.version().major == 0 && c.version().minor == 0 {
+                issues.version_zero = true;
+            }
+            if c.version().is_prerelease() {
+                issues.prerelease = true;
+            }
+
+            issues.empty_inherited = empty_inherited_fields(&root_manifest, c.manifest_path())?;
+            issues.foreign_deps = foreign_deps(&workspace, c);
+            issues.conflicting_deps = conflicting_deps(c);
+            issues.no_rust_version = c.rust_version().is_none();
+            if check.msrv_consistent {
+                issues.rust_version_mismatch = rust_version_mismatch(&root_manifest, c);
+            }
+            issues.version_ahead = upstream
+                .get(c.name().as_str())
+                .and_then(|u| registry::latest_release(u, false))
+                .map(|u| u.as_summary().version())
+                .filter(|published| version_ahead(c.version(), published))
+                .cloned();
+        }
+
+        issues.needs_publish = should_publish.get(c.name().as_str()).map(|deps| {
+            deps.iter()
+                .map(|d| {
+                    workspace
+                        .members()
+                        .find(|c| c.name().as_str() == *d)
+                        .unwrap()
+                })
+                .map(|c| NamePath {
+                    name: c.name().to_string(),
+                    path: c
+                        .root()
+                        .strip_prefix(workspace.root())
+                        .unwrap()
+                        .to_path_buf(),
+                })
+                .collect()
+        });
+
+        all_issues.push(issues);
+    }
+
+    Ok(all_issues)
+}
+
+/// Finds the package fields the crate at `manifest_path` inherits with `field.workspace = true`
+/// that are missing or empty in the root manifest's `[workspace.package]`.
+fn empty_inherited_fields(
+    root_manifest: &toml::Table,
+    manifest_path: &Path,
+) -> Result<Vec<String>> {
+    let manifest: toml::Table = toml::from_str(&read_to_string(manifest_path)?)?;
+    let Some(package) = manifest.get("package").and_then(|p| p.as_table()) else {
+        return Ok(Vec::new());
     };
+    let workspace_package = root_manifest
+        .get("workspace")
+        .and_then(|w| w.get("package"));
+
+    let mut fields = Vec::new();
+    for (field, value) in package {
+        if value.get("workspace").and_then(|w| w.as_bool()) != Some(true) {
+            continue;
+        }
+
+        let empty = match workspace_package.and_then(|p| p.get(field)) {
+            None => true,
+            Some(toml::Value::String(s)) => s.trim().is_empty(),
+            Some(toml::Value::Array(a)) => a.is_empty(),
+            Some(_) => false,
+        };
+        if empty {
+            fields.push(field.clone());
+        }
+    }
+
+    Ok(fields)
+}
+
+/// Finds the members that inherit package fields missing or empty in `[workspace.package]`,
+/// for when cargo can't load the workspace because of them.
+///
+/// The members are found from `workspace.members` and `workspace.exclude` in the root manifest
+/// as there's no workspace to list them.
+fn unloadable_inherited_fields(args: &Args, manifest_path: &Path) -> Result<Vec<Issues>> {
+    let root = manifest_path.parent().context("no parent")?;
+    let root_manifest: toml::Table = toml::from_str(&read_to_string(manifest_path)?)?;
+    let Some(ws) = root_manifest.get("workspace") else {
+        return Ok(Vec::new());
+    };
+    let paths = |key: &str| {
+        ws.get(key)
+            .and_then(|v| v.as_array())
+            .into_iter()
+            .flatten()
+            .filter_map(|v| v.as_str())
+    };
+
+    let mut dirs = BTreeSet::from([root.to_path_buf()]);
+    for member in paths("members") {
+        for dir in glob::glob(&root.join(member).to_string_lossy())? {
+            dirs.insert(dir?);
+        }
+    }
+
+    let mut all_issues = Vec::new();
+    for dir in dirs {
+        let path = dir.strip_prefix(root).unwrap_or(&dir);
+        let manifest = dir.join("Cargo.toml");
+        if paths("exclude").any(|e| path.starts_with(e))
+            || args.exclude_members.iter().any(|p| p.matches_path(path))
+            || !manifest.exists()
+        {
+            continue;
+        }
+
+        let package: toml::Table = toml::from_str(&read_to_string(&manifest)?)?;
+        let Some(name) = package
+            .get("package")
+            .and_then(|p| p.get("name"))
+            .and_then(|n| n.as_str())
+        else {
+            continue;
+        };
 
-    writeln!(stderr, "checking crates....")?;
+        let empty_inherited = empty_inherited_fields(&root_manifest, &manifest)?;
+        if !empty_inherited.is_empty() {
+            all_issues.push(Issues {
+                name: name.to_string(),
+                path: path.to_path_buf(),
+                empty_inherited,
+                ..Issues::default()
+            });
+        }
+    }
+
+    Ok(all_issues)
+}
+
+/// Describes how the crate's rust-version differs from the one in `[workspace.package]`, if the
+/// workspace sets one.
+fn rust_version_mismatch(root_manifest: &toml::Table, c: &Package) -> Option<String> {
+    let msrv = root_manifest
+        .get("workspace")
+        .and_then(|w| w.get("package"))
+        .and_then(|p| p.get("rust-version"))
+        .and_then(|v| v.as_str())?;
+
+    match c.rust_version() {
+        Some(v) if v.to_string() == msrv => None,
+        Some(v) => Some(format!(
+            "rust-version is {} but the workspace's is {}",
+            v, msrv
+        )),
+        None => None,
+    }
+}
+
+/// Finds the non dev git dependencies and path dependencies outside of the workspace, which have
+/// to be rewritten or removed before the crate can be published.
+fn foreign_deps(workspace: &Workspace, c: &Package) -> Vec<String> {
+    let mut deps = Vec::new();
+
+    for dep in c.dependencies() {
+        if dep.kind() == DepKind::Development {
+            continue;
+        }
+
+        let source = dep.source_id();
+        if source.is_git() {
+            deps.push(format!(
+                "depends on {} from git ({})",
+                dep.package_name(),
+                source.url()
+            ));
+        } else if source.is_path()
+            && !workspace
+                .members()
+                .any(|m| m.package_id().source_id() == source)
+        {
+            deps.push(format!(
+                "depends on {} by path outside of the workspace ({})",
+                dep.package_name(),
+                source.url()
+            ));
+        }
+    }
+
+    deps
+}
+
+/// Finds dependencies on the crate itself and dependencies that are declared by path in one
+/// place and from the registry in another, which rewriting the manifests can't reconcile.
+fn conflicting_deps(c: &Package) -> Vec<String> {
+    let mut deps = Vec::new();
+    let mut sources = BTreeMap::<_, BTreeSet<_>>::new();
+
+    for dep in c.dependencies() {
+        if dep.package_name() == c.name() {
+            deps.push(format!(
+                "depends on itself as a {} dependency",
+                dep_kind_name(dep.kind())
+            ));
+        }
+
+        let source = dep.source_id();
+        if source.is_path() || source.is_registry() {
+            sources
+                .entry(dep.package_name().as_str())
+                .or_default()
+                .insert(source.is_path());
+        }
+    }
+
+    for (name, sources) in sources {
+        if sources.len() > 1 {
+            deps.push(format!(
+                "depends on {} both by path and from the registry",
+                name
+            ));
+        }
+    }
+
+    deps
+}
+
+fn dep_kind_name(kind: DepKind) -> &'static str {
+    match kind {
+        DepKind::Normal => "normal",
+        DepKind::Development => "dev",
+        DepKind::Build => "build",
+    }
+}
 
+/// Finds crates that are `publish = false` but are depended on by crates that are published.
+///
+/// Maps each of these crates to the published crates that depend on it.
+pub fn needs_publish<'a>(
+    args: &'a Args,
+    workspace: &'a Workspace,
+    recursive: bool,
+) -> BTreeMap<&'a str, BTreeSet<&'a str>> {
     let mut new_publish = BTreeMap::new();
-    let mut should_publish = workspace
-        .members()
+    let mut should_publish = members(args, workspace)
         .filter(|c| c.publish().is_none())
         .flat_map(|c| c.dependencies())
         .filter(|d| d.kind() != DepKind::Development)
@@ -179,8 +756,7 @@ async fn issues(check: &Check) -> Result<Vec<Issues>> {
         .collect::<BTreeMap<_, BTreeSet<&str>>>();
 
     loop {
-        new_publish = workspace
-            .members()
+        new_publish = members(args, workspace)
             .filter(|c| new_publish.contains_key(c.name().as_str()))
             .flat_map(|c| c.dependencies())
             .filter(|d| d.kind() != DepKind::Development)
@@ -196,14 +772,13 @@ async fn issues(check: &Check) -> Result<Vec<Issues>> {
         new_publish = BTreeMap::new();
     }
 
-    workspace
-        .members()
+    members(args, workspace)
         .filter(|c| c.publish().is_none())
         .for_each(|c| {
             should_publish.remove(c.name().as_str());
         });
 
-    for c in workspace.members() {
+    for c in members(args, workspace) {
         for dep in c
             .dependencies()
             .iter()
@@ -217,10 +792,10 @@ async fn issues(check: &Check) -> Result<Vec<Issues>> {
         }
     }
 
-    if check.recursive {
+    if recursive {
         loop {
             let mut did_something = false;
-            for c in workspace.members() {
+            for c in members(args, workspace) {
                 for dep in c
                     .dependencies()
                     .iter()
@@ -242,76 +817,179 @@ async fn issues(check: &Check) -> Result<Vec<Issues>> {
 
     for deps in should_publish.values_mut() {
         deps.retain(|dep| {
-            workspace
-                .members()
+            members(args, workspace)
                 .find(|c| c.name().as_str() == *dep)
                 .map(|c| c.publish().is_none())
                 .unwrap_or(false)
         })
     }
 
-    for (c, owner) in workspace.members().zip(owners) {
-        let path = c.root().strip_prefix(workspace.root())?;
+    should_publish
+}
 
-        let mut issues = Issues {
-            name: c.name().to_string(),
-            path: path.to_path_buf(),
-            ..Issues::default()
-        };
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shared::test::{gctx, write_tree};
+    use clap::Parser;
 
-        if c.publish().is_none() {
-            match owner {
-                Owner::Us => (),
-                Owner::None => issues.unpublished = true,
-                Owner::Other => issues.taken = true,
-            }
+    #[test]
+    fn unpublished_is_only_fatal_when_denied() {
+        let check = Check::parse_from(["check"]);
+        assert!(!fails(IssueKind::Unpublished, &check));
 
-            issues.no_desc = c.manifest().metadata().description.is_none();
-            issues.no_repo = c.manifest().metadata().repository.is_none();
-            issues.no_license = c.manifest().metadata().license.is_none()
-                && c.manifest().metadata().license_file.is_none();
+        let check = Check::parse_from(["check", "--deny", "unpublished"]);
+        assert!(fails(IssueKind::Unpublished, &check));
+    }
 
-            if let Some(readme) = &c.manifest().metadata().readme {
-                if !c
-                    .manifest_path()
-                    .parent()
-                    .context("no parent")?
-                    .join(readme)
-                    .exists()
-                {
-                    issues.broken_readme = true;
-                }
-            }
+    #[test]
+    fn license_must_be_spdx() {
+        assert_eq!(invalid_license("MIT OR Apache-2.0"), None);
+        assert_eq!(invalid_license("MIT/Apache-2.0"), None);
+        assert_eq!(invalid_license("GPL-2.0+"), None);
 
-            if c.version().major == 0 && c.version().minor == 0 {
-                issues.version_zero = true;
-            }
-            if c.version().is_prerelease() {
-                issues.prerelease = true;
-            }
-        }
+        let err = invalid_license("MIT OR Apache2.0").unwrap();
+        assert!(err.ends_with("'Apache2.0'"), "{}", err);
+    }
 
-        issues.needs_publish = should_publish.get(c.name().as_str()).map(|deps| {
-            deps.iter()
-                .map(|d| {
-                    workspace
-                        .members()
-                        .find(|c| c.name().as_str() == *d)
-                        .unwrap()
-                })
-                .map(|c| NamePath {
-                    name: c.name().to_string(),
-                    path: c
-                        .root()
-                        .strip_prefix(workspace.root())
-                        .unwrap()
-                        .to_path_buf(),
-                })
-                .collect()
-        });
+    fn conflicts(member: &str) -> Vec<String> {
+        let dir = write_tree(&[
+            ("Cargo.toml", "[workspace]\nmembers = [\"a\", \"b\"]\n"),
+            ("a/Cargo.toml", member),
+            ("a/src/lib.rs", ""),
+            (
+                "b/Cargo.toml",
+                "[package]\nname = \"b\"\nversion = \"0.1.0\"\n",
+            ),
+            ("b/src/lib.rs", ""),
+        ]);
+        let gctx = gctx();
+        let workspace = Workspace::new(&dir.path().join("Cargo.toml"), &gctx).unwrap();
+        let a = workspace.members().find(|c| c.name() == "a").unwrap();
+        conflicting_deps(a)
+    }
 
-        all_issues.push(issues);
+    #[test]
+    fn self_dependency_conflicts() {
+        let deps = conflicts(
+            r#"[package]
+name = "a"
+version = "0.1.0"
+
+[dev-dependencies]
+a = "0.1.0"
+"#,
+        );
+        assert_eq!(deps, ["depends on itself as a dev dependency"]);
     }
 
-    Ok(all_issues)
+    #[test]
+    fn path_and_registry_dependency_conflicts() {
+        let deps = conflicts(
+            r#"[package]
+name = "a"
+version = "0.1.0"
+
+[dependencies]
+b = { path = "../b" }
+
+[dev-dependencies]
+published-b = { package = "b", version = "0.1.0" }
+"#,
+        );
+        assert_eq!(deps, ["depends on b both by path and from the registry"]);
+
+        let deps = conflicts(
+            r#"[package]
+name = "a"
+version = "0.1.0"
+
+[dependencies]
+b = { path = "../b", version = "0.1.0" }
+
+[dev-dependencies]
+b = { path = "../b" }
+"#,
+        );
+        assert!(deps.is_empty());
+    }
+
+    async fn check_issues(dir: &Path) -> Vec<Issues> {
+        let args = Args::parse_from([
+            "parity-publish",
+            "--no-progress",
+            "--manifest-path",
+            dir.to_str().unwrap(),
+        ]);
+        let check = Check::parse_from(["check", "--offline"]);
+        issues(&args, &check, None).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn inherited_field_missing_from_root() {
+        let dir = write_tree(&[
+            (
+                "Cargo.toml",
+                r#"[workspace]
+members = ["a", "b"]
+
+[workspace.package]
+license = "Apache-2.0"
+"#,
+            ),
+            (
+                "a/Cargo.toml",
+                r#"[package]
+name = "a"
+version = "0.1.0"
+description.workspace = true
+license.workspace = true
+"#,
+            ),
+            ("a/src/lib.rs", ""),
+            (
+                "b/Cargo.toml",
+                "[package]\nname = \"b\"\nversion = \"0.1.0\"\nlicense.workspace = true\n",
+            ),
+            ("b/src/lib.rs", ""),
+        ]);
+
+        let issues = check_issues(dir.path()).await;
+        assert_eq!(issues.len(), 1);
+        assert_eq!(issues[0].name, "a");
+        assert_eq!(issues[0].path, Path::new("a"));
+        assert_eq!(issues[0].empty_inherited, ["description"]);
+        assert!(issues[0].ret_err(&Check::parse_from(["check"])));
+    }
+
+    #[tokio::test]
+    async fn inherited_field_empty_in_root() {
+        let dir = write_tree(&[
+            (
+                "Cargo.toml",
+                r#"[workspace]
+members = ["a"]
+
+[workspace.package]
+description = "a crate"
+repository = ""
+"#,
+            ),
+            (
+                "a/Cargo.toml",
+                r#"[package]
+name = "a"
+version = "0.1.0"
+description.workspace = true
+repository.workspace = true
+"#,
+            ),
+            ("a/src/lib.rs", ""),
+        ]);
+
+        let issues = check_issues(dir.path()).await;
+        let a = issues.iter().find(|i| i.name == "a").unwrap();
+        assert_eq!(a.empty_inherited, ["repository"]);
+        assert!(!a.no_desc);
+    }
 }
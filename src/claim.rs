@@ -1,28 +1,36 @@
-use std::env::{current_dir, temp_dir};
-use std::fs::{create_dir, remove_dir_all};
+use std::collections::VecDeque;
 use std::io::Write;
-use std::path::PathBuf;
-use std::process::exit;
-use std::sync::Arc;
+use std::process::{exit, Command};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 use std::{env, fs, thread};
 
 use crate::cli::{Args, Claim};
-use crate::shared::{self, get_owners, Owner};
+use crate::shared::{self, get_owners, read_stdin, Cache, Owner};
 
-use anyhow::{Context, Result};
-use cargo::core::resolver::CliFeatures;
+use anyhow::{bail, ensure, Context, Result};
 use cargo::core::Workspace;
-use cargo::ops::{Packages, PublishOpts};
+use crates_io_api::AsyncClient;
+use tempfile::TempDir;
 use termcolor::{Color, ColorSpec, WriteColor};
+use tokio::runtime::Handle;
 
-pub async fn handle_claim(args: Args, claim: Claim) -> Result<()> {
+pub async fn handle_claim(args: Args, mut claim: Claim) -> Result<()> {
     let mut ret = 0;
+    read_stdin(&mut [&mut claim.only, &mut claim.exclude])?;
     let config = cargo::GlobalContext::default()?;
     config.shell().set_verbosity(cargo::core::Verbosity::Quiet);
-    let path = current_dir()?.join("Cargo.toml");
+    let path = args.manifest_path()?;
     let workspace = Workspace::new(&path, &config)?;
-    let token = if claim.dry_run {
+
+    for name in claim.only.iter().chain(&claim.exclude) {
+        if !workspace.members().any(|c| c.name().as_str() == name) {
+            bail!("can't find package '{}'", name);
+        }
+    }
+
+    let token = if claim.dry_run || claim.list {
         String::new()
     } else {
         env::var("PARITY_PUBLISH_CRATESIO_TOKEN")
@@ -32,14 +40,13 @@ pub async fn handle_claim(args: Args, claim: Claim) -> Result<()> {
     let cratesio = Arc::new(shared::cratesio()?);
 
     let mut stdout = args.stdout();
-    let mut stderr = args.stderr();
-    let mut throttle = false;
+    let mut to_claim = Vec::new();
 
-    writeln!(stderr, "looking up crate data, this may take a while....")?;
+    args.progress("looking up crate data, this may take a while....")?;
 
-    let owners = get_owners(&workspace, &cratesio).await;
+    let owners = get_owners(&args, &workspace, &cratesio, &Cache::disabled()).await;
 
-    for (member, owner) in workspace.members().zip(owners) {
+    for (member, owner) in shared::members(&args, &workspace).zip(owners) {
         if member.publish().is_some() {
             stdout.set_color(ColorSpec::new().set_fg(Some(Color::Yellow)))?;
             writeln!(stdout, "{} is set to not publish", member.name())?;
@@ -48,6 +55,11 @@ pub async fn handle_claim(args: Args, claim: Claim) -> Result<()> {
         }
 
         match owner {
+            Owner::Us if claim.list => {
+                stdout.set_color(ColorSpec::new().set_fg(Some(Color::Green)))?;
+                writeln!(stdout, "{} is already owned by us", member.name())?;
+                stdout.set_color(ColorSpec::new().set_fg(None))?;
+            }
             Owner::Us => (),
             Owner::Other => {
                 stdout.set_color(ColorSpec::new().set_fg(Some(Color::Red)))?;
@@ -67,62 +79,188 @@ pub async fn handle_claim(args: Args, claim: Claim) -> Result<()> {
                     continue;
                 }
 
-                let manifest = write_manifest(&member.name())?;
-                let opts = PublishOpts {
-                    gctx: workspace.gctx(),
-                    token: Some(token.clone().into()),
-                    verify: false,
-                    allow_dirty: true,
-                    jobs: None,
-                    keep_going: false,
-                    to_publish: Packages::Default,
-                    targets: Vec::new(),
-                    dry_run: claim.dry_run,
-                    cli_features: CliFeatures {
-                        features: Default::default(),
-                        all_features: false,
-                        uses_default_features: true,
-                    },
-                    reg_or_index: None,
-                };
-                let workspace = Workspace::new(&manifest, &config)?;
-
-                if !throttle && cargo::ops::publish(&workspace, &opts).is_err() {
-                    throttle = true;
+                let name = member.name().to_string();
+                if !claim.only.is_empty() && !claim.only.contains(&name)
+                    || claim.exclude.contains(&name)
+                {
+                    stdout.set_color(ColorSpec::new().set_fg(Some(Color::Yellow)))?;
+                    writeln!(stdout, "{} is excluded from claiming", name)?;
+                    stdout.set_color(ColorSpec::new().set_fg(None))?;
+                    continue;
                 }
 
-                if throttle {
-                    // crates.io rate limit
-                    thread::sleep(Duration::from_secs(60 * 10 + 5));
-                    cargo::ops::publish(&workspace, &opts)?;
+                if claim.list {
+                    stdout.set_color(ColorSpec::new().set_fg(Some(Color::Blue)))?;
+                    writeln!(stdout, "{} would be claimed", member.name())?;
+                    stdout.set_color(ColorSpec::new().set_fg(None))?;
+                    continue;
                 }
 
-                remove_dir_all(manifest.parent().unwrap())?;
-                stdout.set_color(ColorSpec::new().set_fg(Some(Color::Blue)))?;
-                if claim.dry_run {
-                    writeln!(stdout, "published {} (dryrun)", member.name())?;
-                } else {
-                    writeln!(stdout, "published {}", member.name())?;
-                }
-                stdout.set_color(ColorSpec::new().set_fg(None))?;
+                to_claim.push(name);
             }
         }
     }
 
+    let claimed =
+        tokio::task::block_in_place(|| claim_crates(&args, &claim, &token, &to_claim, &cratesio))?;
+    if !claimed {
+        ret = 1;
+    }
+
     exit(ret);
 }
 
-fn write_manifest(name: &str) -> Result<PathBuf> {
-    let dir = temp_dir().join("parity-publish");
-    let manifest = dir.join("Cargo.toml");
-    let _ = remove_dir_all(&dir);
-    create_dir(&dir)?;
+/// Publishes the placeholder releases, up to `--concurrency` at a time.
+///
+/// crates.io only allows a small burst of new crates. Once a publish is rate limited every crate
+/// left is published one at a time, waiting out the rate limit before each. Other failures are
+/// reported straight away.
+///
+/// The owners of each crate are looked up again right before publishing as the run can take
+/// long enough for someone else to publish it first.
+fn claim_crates(
+    args: &Args,
+    claim: &Claim,
+    token: &str,
+    crates: &[String],
+    cratesio: &AsyncClient,
+) -> Result<bool> {
+    let handle = Handle::current();
+    let owner = |name: &str| {
+        shared::owner(handle.block_on(shared::crate_owners(cratesio, &Cache::disabled(), name)))
+    };
+    let queue = Mutex::new(crates.iter().collect::<VecDeque<_>>());
+    let throttled = AtomicBool::new(false);
+    let throttle = Mutex::new(());
+    let failed = AtomicBool::new(false);
+
+    thread::scope(|s| {
+        let workers = (0..(claim.concurrency as usize).min(crates.len()))
+            .map(|_| {
+                s.spawn(|| -> Result<()> {
+                    let mut stdout = args.stdout();
+                    let mut stderr = args.stderr();
+
+                    loop {
+                        let Some(name) = queue.lock().unwrap().pop_front() else {
+                            return Ok(());
+                        };
+
+                        let mut res = None;
+                        if !throttled.load(Ordering::SeqCst) {
+                            let claimed = claim_available(claim, token, name, owner(name));
+                            if claimed.as_ref().is_err_and(rate_limited) {
+                                throttled.store(true, Ordering::SeqCst);
+                            } else {
+                                res = Some(claimed);
+                            }
+                        }
+                        let res = match res {
+                            Some(res) => res,
+                            None => {
+                                let _throttle = throttle.lock().unwrap();
+                                // crates.io rate limit
+                                thread::sleep(Duration::from_secs(60 * 10 + 5));
+                                claim_available(claim, token, name, owner(name))
+                            }
+                        };
 
-    fs::write(dir.join("lib.rs"), "")?;
-    fs::write(dir.join("LICENSE"), "")?;
+                        match res {
+                            Ok(Owner::Us) => {
+                                stdout.set_color(ColorSpec::new().set_fg(Some(Color::Green)))?;
+                                writeln!(stdout, "{} was claimed by us in the meantime", name)?;
+                                stdout.set_color(ColorSpec::new().set_fg(None))?;
+                            }
+                            Ok(Owner::Other) => {
+                                stdout.set_color(ColorSpec::new().set_fg(Some(Color::Red)))?;
+                                writeln!(
+                                    stdout,
+                                    "{} was taken by someone else in the meantime",
+                                    name
+                                )?;
+                                stdout.set_color(ColorSpec::new().set_fg(None))?;
+                                failed.store(true, Ordering::SeqCst);
+                            }
+                            Ok(Owner::None) => {
+                                stdout.set_color(ColorSpec::new().set_fg(Some(Color::Blue)))?;
+                                if claim.dry_run {
+                                    writeln!(stdout, "published {} (dryrun)", name)?;
+                                } else {
+                                    writeln!(stdout, "published {}", name)?;
+                                }
+                                stdout.set_color(ColorSpec::new().set_fg(None))?;
+                            }
+                            Err(e) => {
+                                writeln!(stderr, "failed to claim {}: {:#}", name, e)?;
+                                failed.store(true, Ordering::SeqCst);
+                            }
+                        }
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+
+        workers
+            .into_iter()
+            .try_for_each(|w| w.join().expect("claim thread panicked"))
+    })?;
+
+    Ok(!failed.into_inner())
+}
+
+/// Whether cargo failed to publish because crates.io is rate limiting new crates.
+fn rate_limited(err: &anyhow::Error) -> bool {
+    format!("{:#}", err).contains("(status 429")
+}
+
+/// Publishes the placeholder unless `owner` shows the crate exists by now.
+///
+/// Returns the owner the crate had, so `Owner::None` means it was published.
+fn claim_available(claim: &Claim, token: &str, name: &str, owner: Owner) -> Result<Owner> {
+    if let Owner::None = owner {
+        publish_claim(claim, token, name)?;
+    }
+    Ok(owner)
+}
+
+fn publish_claim(claim: &Claim, token: &str, name: &str) -> Result<()> {
+    let dir = write_manifest(name)?;
+
+    let mut cmd = Command::new(env::var_os("CARGO").unwrap_or_else(|| "cargo".into()));
+    cmd.arg("publish")
+        .arg("--manifest-path")
+        .arg(dir.path().join("Cargo.toml"))
+        .arg("--no-verify")
+        .arg("--allow-dirty");
+    if claim.dry_run {
+        cmd.arg("--dry-run");
+    }
+    if !token.is_empty() {
+        cmd.env("CARGO_REGISTRY_TOKEN", token);
+    }
+
+    let output = cmd.output().context("failed to run cargo publish")?;
+
+    ensure!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr).trim()
+    );
+
+    Ok(())
+}
+
+/// Writes the placeholder crate to a new temporary directory that is removed when dropped.
+fn write_manifest(name: &str) -> Result<TempDir> {
+    let dir = tempfile::Builder::new()
+        .prefix("parity-publish-claim-")
+        .tempdir()?;
+
+    fs::write(dir.path().join("lib.rs"), "")?;
+    fs::write(dir.path().join("LICENSE"), "")?;
 
     fs::write(
-        &manifest,
+        dir.path().join("Cargo.toml"),
         format!(
             r#"
 
@@ -140,5 +278,26 @@ path = "lib.rs"
         ),
     )?;
 
-    Ok(manifest)
+    Ok(dir)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_429_is_rate_limited() {
+        assert!(rate_limited(&anyhow::anyhow!(
+            "error: failed to publish to registry at https://crates.io\n\nCaused by:\n  the remote \
+             server responded with an error (status 429 Too Many Requests): You have published too \
+             many new crates in a short period of time."
+        )));
+        assert!(!rate_limited(&anyhow::anyhow!(
+            "error: the remote server responded with an error (status 400 Bad Request): invalid \
+             crate name"
+        )));
+        assert!(!rate_limited(&anyhow::anyhow!(
+            "failed to run cargo publish"
+        )));
+    }
 }
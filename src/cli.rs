@@ -1,10 +1,12 @@
 use crate::plan::BumpKind;
 use std::{
-    io::{stderr, stdout, IsTerminal},
+    env::current_dir,
+    fmt::Display,
+    io::{self, stderr, stdout, IsTerminal, Write},
     path::PathBuf,
 };
 
-use clap::{ArgAction, Parser};
+use clap::{ArgAction, Parser, ValueEnum};
 use termcolor::{ColorChoice, StandardStream};
 
 fn color(s: &str) -> Result<ColorChoice, &'static str> {
@@ -21,10 +23,22 @@ fn color(s: &str) -> Result<ColorChoice, &'static str> {
 pub struct Args {
     #[arg(long, short = 'C')]
     pub chdir: Option<PathBuf>,
+    /// Path to the Cargo.toml of the workspace to operate on, defaults to the current directory
+    #[arg(long, visible_alias = "workspace-root", value_name = "PATH")]
+    pub manifest_path: Option<PathBuf>,
     #[arg(long, value_parser = color, default_value = "auto")]
     pub color: ColorChoice,
     #[arg(long)]
     pub debug: bool,
+    /// Don't print progress messages to stderr
+    #[arg(long)]
+    pub no_progress: bool,
+    /// Seconds to wait for a git command before killing it, 0 waits forever
+    #[arg(long, default_value_t = 600)]
+    pub git_timeout: u64,
+    /// Ignore workspace members whose path (relative to the workspace root) matches this glob
+    #[arg(long, value_name = "GLOB")]
+    pub exclude_members: Vec<glob::Pattern>,
 }
 
 impl Args {
@@ -34,6 +48,26 @@ impl Args {
     pub fn stderr(&self) -> StandardStream {
         StandardStream::stderr(self.color)
     }
+    /// The `--manifest-path` to pass to `Workspace::new`, a directory is taken to contain it
+    pub fn manifest_path(&self) -> io::Result<PathBuf> {
+        let path = match &self.manifest_path {
+            Some(path) => current_dir()?.join(path),
+            None => current_dir()?,
+        };
+
+        if path.is_dir() {
+            Ok(path.join("Cargo.toml"))
+        } else {
+            Ok(path)
+        }
+    }
+    /// Prints a progress message to stderr unless `--no-progress` is set
+    pub fn progress(&self, msg: impl Display) -> io::Result<()> {
+        if self.no_progress {
+            return Ok(());
+        }
+        writeln!(self.stderr(), "{}", msg)
+    }
 }
 
 /// A tool to help with publishing crates
@@ -70,6 +104,32 @@ pub enum Command {
     Workspace(Workspace),
 }
 
+#[derive(ValueEnum, Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum Format {
+    #[default]
+    Text,
+    Json,
+}
+
+#[derive(ValueEnum, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum GraphFormat {
+    Dot,
+    Mermaid,
+}
+
+#[derive(Parser, Debug, Default)]
+pub struct CacheArgs {
+    /// Cache crates.io responses on disk
+    #[arg(long)]
+    pub cache: bool,
+    /// How long cached crates.io responses are valid for in seconds
+    #[arg(long, default_value_t = 300)]
+    pub cache_ttl: u64,
+    /// Ignore cached crates.io responses and fetch them again
+    #[arg(long)]
+    pub refresh: bool,
+}
+
 #[derive(Parser, Debug)]
 pub struct Status {
     /// Filter to only crates that are not on crates.io
@@ -81,9 +141,29 @@ pub struct Status {
     #[arg(long, short)]
     /// Filter to only crates that do not match the version on crates.io
     pub version: bool,
+    /// Filter to only crates whose newest version on crates.io is yanked
+    #[arg(long)]
+    pub yanked: bool,
     #[arg(long, short)]
     /// Only print crate names
     pub quiet: bool,
+    /// Sort the output instead of printing in workspace order
+    #[arg(long, value_enum)]
+    pub sort: Option<StatusSort>,
+    #[command(flatten)]
+    pub cache: CacheArgs,
+}
+
+#[derive(ValueEnum, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum StatusSort {
+    /// By crate name
+    Name,
+    /// Parity owned crates first, then external, then missing
+    Owner,
+    /// By local version
+    Version,
+    /// Crates that don't match crates.io first
+    Mismatch,
 }
 
 #[derive(Parser, Debug)]
@@ -91,6 +171,22 @@ pub struct Claim {
     /// Don't actually claim crates
     #[arg(long, short)]
     pub dry_run: bool,
+    /// Only list the crates that would be claimed, without running cargo
+    #[arg(long, short)]
+    pub list: bool,
+    /// Maximum number of crates to claim at the same time
+    #[arg(long, default_value_t = 3, value_parser = clap::value_parser!(u64).range(1..))]
+    pub concurrency: u64,
+    /// Only claim this crate, can be given multiple times
+    ///
+    /// `-` reads crate names from stdin one per line
+    #[arg(long, value_name = "CRATE")]
+    pub only: Vec<String>,
+    /// Never claim this crate, can be given multiple times
+    ///
+    /// `-` reads crate names from stdin one per line
+    #[arg(long, value_name = "CRATE")]
+    pub exclude: Vec<String>,
 }
 
 #[derive(Parser, Debug)]
@@ -101,10 +197,28 @@ pub struct Workspace {
     /// Only print crate names
     #[arg(long, short)]
     pub quiet: bool,
-    /// Print packages that own given files
+    /// Print packages that own given files or directories
     #[arg(long, short)]
     pub owns: bool,
-    /// targets to act on
+    /// Print packages whose root is within the given directories
+    #[arg(long, conflicts_with = "owns")]
+    pub members_of_path: bool,
+    /// Print publishable packages that have never been published to crates.io
+    #[arg(long, conflicts_with_all = ["owns", "members_of_path"])]
+    pub unpublished: bool,
+    /// Print dependency cycles between packages, which make publishing impossible
+    #[arg(long, conflicts_with_all = ["owns", "members_of_path", "unpublished"])]
+    pub cycles: bool,
+    /// Print the features of the given packages and what each feature enables
+    #[arg(long, conflicts_with_all = ["owns", "members_of_path", "unpublished", "cycles"])]
+    pub features: bool,
+    /// Print the dependencies of the given packages with their version requirements and sources
+    #[arg(
+        long,
+        conflicts_with_all = ["owns", "members_of_path", "unpublished", "cycles", "features"]
+    )]
+    pub deps: bool,
+    /// targets to act on, `-` reads them from stdin one per line
     #[arg(default_values_t = Vec::<String>::new())]
     pub targets: Vec<String>,
 }
@@ -123,16 +237,61 @@ pub struct Semver {
     /// Verbose output
     #[arg(long, short)]
     pub verbose: bool,
-    /// Old version to compare against
-    #[arg(long)]
+    /// Print every item added to the public API of each crate, for writing changelogs
+    #[arg(long, conflicts_with_all = ["major", "verbose"])]
+    pub additions_only: bool,
+    /// Old version to compare against, `@base` for the merge-base with the base branch
+    #[arg(long, group = "baseline")]
     pub since: Option<String>,
+    /// Compare each crate against the git tag of its latest release
+    #[arg(long, group = "baseline")]
+    pub since_release: bool,
+    /// The git tag a release is tagged with, `{crate}` and `{version}` are replaced with the
+    /// package's name and version. Can be passed multiple times to try each pattern in order
+    #[arg(
+        long,
+        value_name = "PATTERN",
+        default_value = "{crate}-v{version}",
+        requires = "since_release"
+    )]
+    pub from_tag_pattern: Vec<String>,
+    /// New version to compare, instead of the working tree
+    #[arg(long, requires = "baseline")]
+    pub to: Option<String>,
+    /// Compare against this published version instead of the latest release
+    #[arg(long, conflicts_with = "baseline")]
+    pub baseline_version: Option<semver::Version>,
     /// Rust toolchain to use
     #[arg(long, default_value = public_api::MINIMUM_NIGHTLY_RUST_VERSION)]
     pub toolchain: String,
+    /// Number of crates to build at once, 0 builds as many as there are cpus
+    ///
+    /// Rustdoc json for large crates uses a lot of memory so this defaults to one at a time. Each
+    /// build at once gets its own target directory, which takes up that much more disk
+    #[arg(long, default_value_t = 1)]
+    pub jobs: usize,
     /// Print the minimum nightly rust version needed for semver checks
     #[arg(long)]
     pub minimum_nightly_rust_version: bool,
-    /// Crates to check
+    /// Exit 1 if any crate has a change of at least this level that its planned version
+    /// doesn't already cover
+    #[arg(long, value_enum)]
+    pub fail_on: Option<BumpKind>,
+    /// Plan whose versions --fail-on takes as already bumped, it's fine if it doesn't exist
+    #[arg(long, default_value = "Plan.toml")]
+    pub plan: PathBuf,
+    /// Check this crate and everything in the workspace that depends on it
+    ///
+    /// `-` reads crate names from stdin one per line
+    #[arg(long, value_name = "CRATE")]
+    pub dependents_of: Vec<String>,
+    /// Cache the rustdoc json of published versions on disk
+    ///
+    /// Entries are keyed by the exact `rustc --version` of the toolchain so updating the
+    /// toolchain doesn't reuse json built by the old one
+    #[arg(long)]
+    pub rustdoc_cache: bool,
+    /// Crates to check, `-` reads them from stdin one per line
     #[arg(default_values_t = Vec::<String>::new())]
     pub crates: Vec<String>,
 }
@@ -154,23 +313,39 @@ pub struct Prdoc {
     /// Only print crate names
     #[arg(long, short)]
     pub quiet: bool,
-    /// Validate crate changes specified in prdocs
+    /// The git ref to validate prdocs against, `@base` for the merge-base with the base branch
     #[arg(long)]
     pub since: Option<String>,
     /// Validate crate changes specified in prdocs
     #[arg(long)]
     pub validate: bool,
+    /// Print validation results as json
+    #[arg(long, requires = "validate")]
+    pub json: bool,
+    /// Validate the crates listed in this file against the bump given there instead of the
+    /// predicted one
+    ///
+    /// Each override is a `[[crate]]` table with the crate's `name`, the `bump` it needs and the
+    /// `reason` it needs it
+    #[arg(long, requires = "validate", value_name = "PATH")]
+    pub bump_overrides: Option<PathBuf>,
     /// Path to prdoc dir
     pub prdoc_path: PathBuf,
-    /// Limit output to specified crates
     /// Rust toolchain to use
     #[arg(long, default_value = public_api::MINIMUM_NIGHTLY_RUST_VERSION)]
     pub toolchain: String,
+    /// Limit output to specified crates, `-` reads them from stdin one per line
     #[arg(default_values_t = Vec::<String>::new())]
     pub crates: Vec<String>,
     /// The maximum bump that is allowed for any crate to happen. Only checked if `validate` is set.
     #[arg(long, value_enum)]
     pub max_bump: Option<BumpKind>,
+    /// Number of crates to build at once, 0 builds as many as there are cpus
+    ///
+    /// Rustdoc json for large crates uses a lot of memory so this defaults to one at a time. Each
+    /// build at once gets its own target directory, which takes up that much more disk
+    #[arg(long, default_value_t = 1)]
+    pub jobs: usize,
 }
 
 #[derive(Parser, Debug)]
@@ -190,8 +365,30 @@ pub struct Changed {
     /// Only show packages where the manifest changed
     #[arg(long, short)]
     pub manifests: bool,
-    /// The git commit to look for changes from
-    pub from: String,
+    /// Output format
+    #[arg(long, value_enum, default_value_t = Format::Text)]
+    pub format: Format,
+    /// Show the dependency path from each indirectly changed package to the change that caused it
+    #[arg(long, visible_alias = "dump-graph", conflicts_with = "no_deps")]
+    pub explain: bool,
+    /// Look for changes to each package since the git tag of its latest release
+    #[arg(long, conflicts_with = "from")]
+    pub since_release: bool,
+    /// The git tag a release is tagged with, `{crate}` and `{version}` are replaced with the
+    /// package's name and version. Can be passed multiple times to try each pattern in order
+    #[arg(
+        long,
+        value_name = "PATTERN",
+        default_value = "{crate}-v{version}",
+        requires = "since_release"
+    )]
+    pub from_tag_pattern: Vec<String>,
+    /// The git commit to look for changes to with `--since-release`
+    #[arg(long = "to", value_name = "TO", requires = "since_release")]
+    pub release_to: Option<String>,
+    /// The git commit to look for changes from, `@base` for the merge-base with the base branch
+    #[arg(required_unless_present = "since_release")]
+    pub from: Option<String>,
     /// The git commit to look for changes to
     #[arg(default_value = "HEAD")]
     pub to: String,
@@ -208,7 +405,8 @@ pub struct Plan {
     /// publish all crates
     #[arg(long, short)]
     pub all: bool,
-    /// Publish crates that have changed since git ref
+    /// Publish crates that have changed since git ref, `@base` for the merge-base with the base
+    /// branch
     #[arg(long)]
     pub since: Option<String>,
     #[arg(long)]
@@ -229,9 +427,49 @@ pub struct Plan {
     /// Print expanded plan
     #[arg(long)]
     pub print_expanded: bool,
-    /// Don't bump versions when generating plan
+    /// Print a table of the crates the existing plan publishes and why
+    #[arg(long, conflicts_with = "new")]
+    pub summary: bool,
+    /// Record changes and bump kinds but keep the current versions
     #[arg(long)]
     pub hold_version: bool,
+    /// Update `from` of crates already in the plan to their latest release, keeping other edits
+    #[arg(long)]
+    pub refresh_from: bool,
+    /// Give every crate that depends on a changed crate at least a patch bump, instead of only
+    /// the dependents of crates with a major bump
+    #[arg(long)]
+    pub propagate_all: bool,
+    /// The maximum bump any crate will be given. Crates that need more are capped and flagged
+    #[arg(long, value_enum)]
+    pub max_bump: Option<BumpKind>,
+    /// Allow picking version numbers that were published and then yanked
+    ///
+    /// By default yanked versions are treated as taken. crates.io never allows a version to be
+    /// uploaded twice, even if it was yanked, so only use this for registries that do
+    #[arg(long)]
+    pub reclaim_yanked: bool,
+    /// Print the dependency graph of the workspace in the given format instead of planning
+    #[arg(long, value_enum)]
+    pub graph_format: Option<GraphFormat>,
+    /// Path to write the plan to
+    #[arg(long, short, default_value = "Plan.toml")]
+    pub output: PathBuf,
+    /// Fail without writing the plan if planning printed any warnings
+    #[arg(long)]
+    pub strict: bool,
+    /// Read more crates from a file with one crate name per line
+    #[arg(long, value_name = "PATH")]
+    pub crates_file: Option<PathBuf>,
+    /// Add the specified crates to the named release set so they can be applied separately
+    #[arg(long, value_name = "NAME", conflicts_with_all = ["new", "patch"])]
+    pub set: Option<String>,
+    /// Leave crates that are unchanged and already published out of the plan file
+    ///
+    /// This is remembered by the plan, regenerate it with --new to list every crate again
+    #[arg(long)]
+    pub exclude_unchanged: bool,
+    /// Crates to plan, `-` reads them from stdin one per line
     pub crates: Vec<String>,
 }
 
@@ -243,18 +481,106 @@ pub struct Apply {
     /// Publish the crates
     #[arg(long, short)]
     pub publish: bool,
+    /// Publish the manifests as they are without rewriting them first
+    #[arg(long, requires = "publish")]
+    pub no_rewrite: bool,
     /// Allow dirty working directories to be published
     #[arg(long)]
     pub allow_dirty: bool,
     /// Don't verify packages before publish
     #[arg(long)]
     pub no_verify: bool,
+    /// Before publishing, check the toolchain has the components and targets from
+    /// rust-toolchain.toml and that the first crate to publish builds
+    #[arg(long, requires = "publish")]
+    pub preflight: bool,
     /// Use registry for dependencies instead of local paths
     #[arg(long)]
     pub registry: bool,
+    /// Always depend on workspace members by their planned version and drop local paths
+    #[arg(long, conflicts_with = "registry")]
+    pub registry_only: bool,
+    /// Give path dev dependencies on members their planned version instead of letting cargo strip
+    /// them when publishing, the members are then published first. Members that depend back on
+    /// the crate, even as a dev dependency, are still stripped as that cycle can't be published
+    #[arg(long)]
+    pub version_dev_deps: bool,
+    /// Publish to this registry from cargo's config instead of crates.io
+    #[arg(long, value_name = "NAME", conflicts_with = "registry_url")]
+    pub registry_name: Option<String>,
+    /// Publish to the registry with this index URL instead of crates.io
+    #[arg(long)]
+    pub registry_url: Option<String>,
     /// Print packages that need publish
     #[arg(long)]
     pub print: bool,
+    /// Print how long each phase took at the end
+    #[arg(long)]
+    pub profile: bool,
+    /// Also write the output of publishing each crate to <DIR>/<crate>.log
+    ///
+    /// Crates are published with a cargo subprocess to capture its output even without
+    /// --max-concurrent.
+    #[arg(long, value_name = "DIR")]
+    pub log_dir: Option<PathBuf>,
+    /// Run `cargo package` for each crate after rewriting instead of publishing, no token is needed
+    #[arg(long, conflicts_with = "publish", requires = "output_dir")]
+    pub package_only: bool,
+    /// Directory to copy the .crate files made by --package-only to
+    #[arg(long, value_name = "DIR", requires = "package_only")]
+    pub output_dir: Option<PathBuf>,
+    /// Only publish crates in this release set
+    #[arg(long, value_name = "NAME")]
+    pub set: Option<String>,
+    /// Path to the plan to apply
+    #[arg(long, default_value = "Plan.toml")]
+    pub plan: PathBuf,
+    /// Seconds to wait after publishing a crate before publishing the next one
+    ///
+    /// With --max-concurrent > 1 each concurrent publish waits independently so up to
+    /// --max-concurrent crates are still published per delay period.
+    #[arg(long, default_value_t = 60)]
+    pub between_crate_delay: u64,
+    /// Maximum number of crates in a batch. Crates only depend on crates in earlier batches
+    #[arg(long, default_value_t = 10, value_parser = clap::value_parser!(u64).range(1..))]
+    pub batch_size: u64,
+    /// Maximum number of crates to publish at the same time within a batch
+    #[arg(long, default_value_t = 3, value_parser = clap::value_parser!(u64).range(1..))]
+    pub max_concurrent: u64,
+    /// Seconds to wait between publishing batches
+    #[arg(long, default_value_t = 120)]
+    pub batch_delay: u64,
+    /// Number of independent batches to publish at the same time (0 = one batch at a time)
+    #[arg(long, default_value_t = 0)]
+    pub parallel_batches: u64,
+    /// Seconds to wait between checking the registry for published crates
+    #[arg(long, default_value_t = 5)]
+    pub poll_interval: u64,
+    /// Seconds to wait for published crates to show up in the registry
+    #[arg(long, default_value_t = 300)]
+    pub poll_timeout: u64,
+    /// Retry crates that fail because a crate published earlier in the run is not in the index yet
+    ///
+    /// The wait before each retry starts at --poll-interval and doubles each time, up to
+    /// --poll-timeout
+    #[arg(long)]
+    pub continue_on_index_lag: bool,
+    /// Times to retry a crate with --continue-on-index-lag
+    #[arg(long, default_value_t = 5, requires = "continue_on_index_lag")]
+    pub index_lag_retries: u32,
+    /// Don't check the registry for already published crates, try to publish every crate in
+    /// the plan and skip the ones cargo reports as already published. The index is still polled
+    /// between batches
+    #[arg(long)]
+    pub skip_published_check: bool,
+    /// Skip crates whose planned version is already published instead of failing, for running
+    /// apply again after it stopped partway
+    #[arg(long)]
+    pub allow_published: bool,
+    /// Keep publishing crates that don't depend on a crate that failed to publish and print what
+    /// was published, what failed and what was skipped because of it at the end
+    #[arg(long)]
+    pub keep_going: bool,
 }
 
 #[derive(Parser, Debug)]
@@ -272,11 +598,49 @@ pub struct Check {
     /// Dont check ownership status
     pub no_check_owner: bool,
     #[arg(long)]
+    /// Dont access the network, only run the local checks
+    pub offline: bool,
+    #[arg(long)]
     /// Dont exit 1 when crate is unpublished
+    ///
+    /// Kept for compatibility, unpublished crates are only fatal with `--deny unpublished`
     pub allow_unpublished: bool,
     #[arg(long, short)]
     /// recursively find what crates depend on unpublished crates
     pub recursive: bool,
+    #[arg(long)]
+    /// Check rust-version is the one set in [workspace.package]
+    pub msrv_consistent: bool,
+    #[arg(long, value_enum, value_name = "ISSUE")]
+    /// Dont exit 1 for this kind of issue
+    pub allow: Vec<IssueKind>,
+    #[arg(long, value_enum, value_name = "ISSUE")]
+    /// Exit 1 for this kind of issue even if it's allowed
+    pub deny: Vec<IssueKind>,
+    #[command(flatten)]
+    pub cache: CacheArgs,
+}
+
+#[derive(ValueEnum, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum IssueKind {
+    NoDesc,
+    NoRepo,
+    NoLicense,
+    InvalidLicense,
+    Unpublished,
+    Taken,
+    BrokenReadme,
+    MissingTarget,
+    VersionZero,
+    Prerelease,
+    ExcludedFiles,
+    EmptyInherited,
+    ForeignDeps,
+    ConflictingDeps,
+    NoRustVersion,
+    RustVersionMismatch,
+    VersionAhead,
+    NeedsPublish,
 }
 
 #[derive(Parser, Debug)]
@@ -284,4 +648,7 @@ pub struct Config {
     #[arg(long)]
     /// Apply changes specified in Plan.config
     pub apply: bool,
+    /// Print the changes applying Plan.config would make to each manifest instead of making them
+    #[arg(long, requires = "apply")]
+    pub dry_run: bool,
 }
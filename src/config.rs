@@ -1,12 +1,19 @@
-use std::{env::current_dir, fs::read_to_string, path::Path, str::FromStr};
+use std::{
+    fs::read_to_string,
+    io::Write,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
 
 use anyhow::{Context, Result};
-use cargo::{core::Workspace, util::toml_mut::manifest::LocalManifest};
+use cargo::core::Workspace;
+use termcolor::{Color, ColorSpec, StandardStream, WriteColor};
+use toml_edit::DocumentMut;
 
 use crate::{
     cli::{self, Args},
     edit,
-    plan::{RemoveCrate, RemoveDep, RemoveFeature},
+    plan::{RemoveCrate, RemoveDep, RemoveFeature, RemoveFeatureValue},
 };
 
 #[derive(serde::Serialize, serde::Deserialize, Default)]
@@ -17,6 +24,9 @@ pub struct Crate {
     pub remove_feature: Vec<RemoveFeature>,
     #[serde(skip_serializing_if = "Vec::is_empty")]
     #[serde(default)]
+    pub remove_feature_value: Vec<RemoveFeatureValue>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    #[serde(default)]
     pub remove_dep: Vec<RemoveDep>,
 }
 
@@ -32,57 +42,162 @@ pub struct Config {
     pub remove_crates: Vec<RemoveCrate>,
 }
 
-pub fn handle_config(_args: Args, cli: cli::Config) -> Result<()> {
-    let path = current_dir()?;
-    let config = read_config(&path)?;
+impl Config {
+    /// The number of feature and dependency removals across all crates.
+    pub fn edits(&self) -> usize {
+        self.crates
+            .iter()
+            .map(|c| c.remove_feature.len() + c.remove_feature_value.len() + c.remove_dep.len())
+            .sum()
+    }
+}
 
+pub fn handle_config(args: Args, cli: cli::Config) -> Result<()> {
     let cargo_config = cargo::GlobalContext::default()?;
     cargo_config
         .shell()
         .set_verbosity(cargo::core::Verbosity::Quiet);
 
-    let workspace = Workspace::new(&path.join("Cargo.toml"), &cargo_config)?;
+    let workspace = Workspace::new(&args.manifest_path()?, &cargo_config)?;
+    let config = read_config(workspace.root())?;
 
-    if cli.apply {
+    if cli.apply && cli.dry_run {
+        dry_run(&args, &workspace, &config)?;
+    } else if cli.apply {
         apply_config(&workspace, &config)?;
     }
 
     Ok(())
 }
 
+/// Makes the config's edits to copies of the manifests and prints how each file would change.
+fn dry_run(args: &Args, workspace: &Workspace, config: &Config) -> Result<()> {
+    let mut stdout = args.stdout();
+    let root_path = workspace.root_manifest();
+    let original_root = read_to_string(root_path)?;
+    let (root_manifest, manifests) = edit_config(workspace, config, &original_root)?;
+    let root_manifest = root_manifest.to_string();
+    let root = (root_path, original_root.as_str(), root_manifest);
+
+    for (path, old, new) in std::iter::once(root).chain(manifests.changes()) {
+        if old == new {
+            continue;
+        }
+
+        let path = path.strip_prefix(workspace.root()).unwrap_or(path);
+        stdout.set_color(ColorSpec::new().set_bold(true))?;
+        writeln!(stdout, "{}:", path.display())?;
+        stdout.set_color(&ColorSpec::new())?;
+        print_diff(&mut stdout, old, &new)?;
+        writeln!(stdout)?;
+    }
+
+    Ok(())
+}
+
+/// Prints the lines removed from `old` and added in `new`.
+fn print_diff(stdout: &mut StandardStream, old: &str, new: &str) -> Result<()> {
+    let old = old.lines().collect::<Vec<_>>();
+    let new = new.lines().collect::<Vec<_>>();
+
+    // longest common subsequence of the lines after each point
+    let mut lcs = vec![vec![0; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lcs[i][j] = if old[i] == new[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            i += 1;
+            j += 1;
+        } else if j == new.len() || (i < old.len() && lcs[i + 1][j] >= lcs[i][j + 1]) {
+            stdout.set_color(ColorSpec::new().set_fg(Some(Color::Red)))?;
+            writeln!(stdout, "-{}", old[i])?;
+            i += 1;
+        } else {
+            stdout.set_color(ColorSpec::new().set_fg(Some(Color::Green)))?;
+            writeln!(stdout, "+{}", new[j])?;
+            j += 1;
+        }
+    }
+    stdout.set_color(&ColorSpec::new())?;
+
+    Ok(())
+}
+
 pub fn apply_config(workspace: &Workspace, config: &Config) -> Result<()> {
-    for pkg in &config.remove_crates {
-        edit::remove_crate(&workspace, pkg)?;
+    let original_root = read_to_string(workspace.root_manifest())?;
+    let (root_manifest, manifests) = edit_config(workspace, config, &original_root)?;
+    manifests.write()?;
+    let root_manifest = root_manifest.to_string();
+    if root_manifest != original_root {
+        std::fs::write(workspace.root_manifest(), root_manifest)?;
     }
 
-    let root_manifest = std::fs::read_to_string(workspace.root_manifest())?;
-    let mut root_manifest = toml_edit::DocumentMut::from_str(&root_manifest)?;
+    Ok(())
+}
+
+/// Makes the config's edits in memory, returning the edited root manifest and members.
+fn edit_config(
+    workspace: &Workspace,
+    config: &Config,
+    root_manifest: &str,
+) -> Result<(DocumentMut, edit::Manifests)> {
+    let mut root_manifest = DocumentMut::from_str(root_manifest)?;
+    let mut manifests = edit::Manifests::default();
+
+    for pkg in &config.remove_crates {
+        edit::remove_crate_inner(workspace, &mut root_manifest, &mut manifests, pkg)?;
+    }
 
     for pkg in &config.crates {
         let c = workspace
             .members()
             .find(|c| c.name().as_str() == pkg.name)
             .context("can't find crate")?;
-        let path = c.root();
-        let mut manifest = LocalManifest::try_new(&path.join(path).join("Cargo.toml"))?;
+        let path = c.manifest_path();
 
         for remove_feature in &pkg.remove_feature {
-            edit::remove_feature(&mut manifest, remove_feature)?;
+            edit::remove_feature(
+                workspace,
+                &mut root_manifest,
+                &mut manifests,
+                path,
+                remove_feature,
+            )?;
         }
 
-        for remove_dep in &pkg.remove_dep {
-            edit::remove_dep(&workspace, &mut root_manifest, &mut manifest, remove_dep)?;
+        for remove_value in &pkg.remove_feature_value {
+            edit::remove_feature_value(manifests.get(path)?, remove_value)?;
         }
 
-        manifest.write()?;
-        std::fs::write(workspace.root_manifest(), &root_manifest.to_string())?;
+        for remove_dep in &pkg.remove_dep {
+            edit::remove_dep(
+                workspace,
+                &mut root_manifest,
+                &mut manifests,
+                path,
+                remove_dep,
+            )?;
+        }
     }
 
-    Ok(())
+    Ok((root_manifest, manifests))
+}
+
+pub fn config_path(path: &Path) -> PathBuf {
+    path.join("Plan.config")
 }
 
 pub fn read_config(path: &Path) -> Result<Config> {
-    let path = path.join("Plan.config");
+    let path = config_path(path);
 
     if !path.exists() {
         return Ok(Default::default());
@@ -92,3 +207,51 @@ pub fn read_config(path: &Path) -> Result<Config> {
     let config = toml::from_str(&config)?;
     Ok(config)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shared::test::{gctx, write_tree};
+
+    #[test]
+    fn edit_config_leaves_files_alone() {
+        let manifest = r#"[package]
+name = "a"
+version = "0.1.0"
+
+[dependencies]
+b = { path = "../b", version = "0.1.0", optional = true }
+
+[features]
+extra = ["dep:b"]
+"#;
+        let dir = write_tree(&[
+            ("Cargo.toml", "[workspace]\nmembers = [\"a\", \"b\"]\n"),
+            ("a/Cargo.toml", manifest),
+            ("a/src/lib.rs", ""),
+            (
+                "b/Cargo.toml",
+                "[package]\nname = \"b\"\nversion = \"0.1.0\"\n",
+            ),
+            ("b/src/lib.rs", ""),
+            (
+                "Plan.config",
+                "[[crate]]\nname = \"a\"\n\n[[crate.remove_dep]]\nname = \"b\"\n",
+            ),
+        ]);
+        let gctx = gctx();
+        let workspace = Workspace::new(&dir.path().join("Cargo.toml"), &gctx).unwrap();
+        let config = read_config(workspace.root()).unwrap();
+        let root = read_to_string(workspace.root_manifest()).unwrap();
+
+        let (_, manifests) = edit_config(&workspace, &config, &root).unwrap();
+
+        let changes = manifests.changes().collect::<Vec<_>>();
+        assert_eq!(changes.len(), 1);
+        let (path, old, new) = &changes[0];
+        assert_eq!(*old, manifest);
+        assert!(!new.contains("[dependencies]\nb ="), "{}", new);
+        assert!(!new.contains("extra"), "{}", new);
+        assert_eq!(read_to_string(path).unwrap(), manifest);
+    }
+}
@@ -1,6 +1,5 @@
-use std::collections::BTreeMap;
-use std::fs::read_to_string;
-use std::path::Path;
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
 
 use anyhow::{Context, Result};
 use cargo::core::{FeatureValue, Features, Package, Workspace};
@@ -11,98 +10,155 @@ use cargo::{core::dependency::DepKind, util::toml_mut::dependency::PathSource};
 use semver::{Version, VersionReq};
 use toml_edit::{DocumentMut, Formatted};
 
-use crate::plan::{Planner, RemoveCrate, RemoveDep, RemoveFeature, RewriteDep};
+use crate::plan::{Planner, RemoveCrate, RemoveDep, RemoveFeature, RemoveFeatureValue, RewriteDep};
 
+/// How dependencies on other workspace members are written when rewriting manifests.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum DepSource {
+    /// Depend on the member by path and version.
+    Path,
+    /// Depend on the member from the registry if the version is already published, otherwise
+    /// by path and version.
+    Registry,
+    /// Always depend on the member from the registry, dropping the path. Dev dependencies keep
+    /// their path so cargo can strip them when publishing.
+    RegistryOnly,
+}
+
+impl DepSource {
+    fn use_registry(
+        self,
+        pkg: &Package,
+        upstream: &BTreeMap<String, Vec<IndexSummary>>,
+        ver: &VersionReq,
+        dev: bool,
+    ) -> bool {
+        match self {
+            DepSource::Path => false,
+            DepSource::Registry => {
+                pkg.publish().is_none()
+                    && upstream
+                        .get(pkg.name().as_str())
+                        .is_some_and(|d| d.iter().any(|d| ver.matches(d.as_summary().version())))
+            }
+            DepSource::RegistryOnly => !dev && pkg.publish().is_none(),
+        }
+    }
+}
+
+/// Points a dev dependency that is inherited from the workspace at the member's path.
 pub fn rewrite_workspace_dep(
     workspace_path: &Path,
-    plan: &Planner,
-    root_manifest: &mut DocumentMut,
-    workspace_crates: &BTreeMap<&str, &Package>,
-    upstream: &BTreeMap<String, Vec<IndexSummary>>,
+    root_manifest: &DocumentMut,
     dep: &RewriteDep,
     cdep: &mut Dependency,
-    dev: bool,
-    use_registry: bool,
 ) -> Result<()> {
-    let wdeps = root_manifest
-        .get_mut("workspace")
-        .unwrap()
-        .get_mut("dependencies")
-        .unwrap();
-
-    let wdep = wdeps.get_mut(&dep.name).unwrap();
+    let wdep = root_manifest
+        .get("workspace")
+        .and_then(|w| w.get("dependencies"))
+        .and_then(|d| d.get(&dep.name))
+        .context("cant find workspace dependency ".to_string() + &dep.name)?;
     let name = if let Some(package) = wdep.get("package") {
         package.as_value().unwrap().as_str().unwrap()
     } else {
         dep.name.as_str()
     };
+
+    let default_features = wdep.get("default-features").map(|d| d.as_bool().unwrap());
+    if let Some(path) = wdep.get("path") {
+        let path = workspace_path.join(path.as_str().unwrap());
+        let source = PathSource::new(&path);
+        *cdep = cdep.clone().set_source(source);
+        if default_features == Some(false) && cdep.default_features != Some(true) {
+            *cdep = cdep.clone().set_default_features(false);
+        }
+        // features enabled by the workspace entry apply on top of the member's own
+        let features = wdep
+            .get("features")
+            .and_then(|f| f.as_array())
+            .into_iter()
+            .flatten()
+            .filter_map(|f| f.as_str())
+            .map(|f| f.to_string())
+            .collect::<Vec<_>>();
+        if !features.is_empty() {
+            *cdep = cdep.clone().extend_features(features);
+        }
+        if dep.name != name {
+            cdep.name = name.to_string();
+            *cdep = cdep.clone().set_rename(&dep.name);
+        }
+    }
+    Ok(())
+}
+
+/// Sets the version, and path if needed, of an entry in the root `[workspace.dependencies]`.
+pub fn bump_workspace_dependency(
+    workspace_path: &Path,
+    plan: &Planner,
+    root_manifest: &mut DocumentMut,
+    workspace_crates: &BTreeMap<&str, &Package>,
+    upstream: &BTreeMap<String, Vec<IndexSummary>>,
+    dep: &RewriteDep,
+    dep_source: DepSource,
+) -> Result<()> {
+    let wdep = root_manifest
+        .get_mut("workspace")
+        .and_then(|w| w.get_mut("dependencies"))
+        .and_then(|d| d.get_mut(&dep.name))
+        .and_then(|d| d.as_inline_table_mut())
+        .context("cant find workspace dependency ".to_string() + &dep.name)?;
+    let name = wdep
+        .get("package")
+        .map(|d| d.as_str().unwrap())
+        .unwrap_or_else(|| &dep.name);
     let new_ver = if let Some(v) = &dep.version {
         v.to_string()
     } else {
         plan.crates
             .iter()
             .find(|c| c.name == name)
+            .map(|c| c.to.clone())
+            .or_else(|| workspace_crates.get(name).map(|c| c.version().to_string()))
             .context("cant find package ".to_string() + name)?
-            .to
-            .clone()
     };
 
-    if dev {
-        let default_features = wdep.get("default-features").map(|d| d.as_bool().unwrap());
-        if let Some(path) = wdep.get("path") {
-            let path = path.as_str().unwrap();
-            let path = Path::new(path).canonicalize().unwrap();
-            let source = PathSource::new(&path);
-            *cdep = cdep.clone().set_source(source);
-            if default_features == Some(false) && cdep.default_features != Some(true) {
-                *cdep = cdep.clone().set_default_features(false);
-            }
-            if dep.name != name {
-                cdep.name = name.to_string();
-                *cdep = cdep.clone().set_rename(&dep.name);
-            }
-        }
-    } else {
-        let wdep = wdep.as_inline_table_mut().unwrap();
-        let name = wdep
-            .get("package")
-            .map(|d| d.as_str().unwrap())
-            .unwrap_or_else(|| &dep.name);
-        let ver = VersionReq::parse(&new_ver).unwrap();
-        if let Some(pkg) = workspace_crates.get(name) {
-            if pkg.publish().is_none()
-                && use_registry
-                && upstream
-                    .get(name)
-                    .and_then(|d| d.iter().find(|d| ver.matches(d.as_summary().version())))
-                    .is_some()
-            {
-                let _ = wdep.remove("path");
-            } else {
-                let path = pkg.root().strip_prefix(workspace_path).unwrap();
-                wdep.insert(
-                    "path",
-                    toml_edit::Value::String(Formatted::new(path.to_str().unwrap().to_string())),
-                );
-            }
+    let ver = VersionReq::parse(&new_ver).unwrap();
+    if let Some(pkg) = workspace_crates.get(name) {
+        if dep_source.use_registry(pkg, upstream, &ver, false) {
+            let _ = wdep.remove("path");
+        } else {
+            let path = pkg.root().strip_prefix(workspace_path).unwrap();
+            wdep.insert(
+                "path",
+                toml_edit::Value::String(Formatted::new(path.to_str().unwrap().to_string())),
+            );
         }
-        wdep.insert("version", toml_edit::Value::String(Formatted::new(new_ver)));
-        wdep.fmt();
     }
+    wdep.insert("version", toml_edit::Value::String(Formatted::new(new_ver)));
+    wdep.fmt();
     Ok(())
 }
 
+/// Rewrites the dependencies of a member.
+///
+/// Dependencies inherited from the workspace are shared between members so they are returned
+/// instead, to be bumped once with [`bump_workspace_dependency`].
 pub fn rewrite_deps(
     workspace: &Workspace,
     workspace_path: &Path,
     plan: &Planner,
-    root_manifest: &mut DocumentMut,
+    root_manifest: &DocumentMut,
     manifest: &mut LocalManifest,
     workspace_crates: &BTreeMap<&str, &Package>,
     upstream: &BTreeMap<String, Vec<IndexSummary>>,
     deps: &[RewriteDep],
-    use_registry: bool,
-) -> Result<()> {
+    dep_source: DepSource,
+    version_dev_deps: bool,
+) -> Result<Vec<RewriteDep>> {
+    let mut workspace_deps = Vec::new();
+    let member = manifest.package_name()?.to_string();
+
     for dep in deps {
         let exisiting_deps = manifest
             .get_dependency_versions(&dep.name, workspace, &Features::default())
@@ -124,17 +180,16 @@ pub fn rewrite_deps(
                     .source()
                     .map_or(false, |d| d.as_workspace().is_some());
                 if is_workspace {
-                    rewrite_workspace_dep(
-                        workspace_path,
-                        plan,
-                        root_manifest,
-                        workspace_crates,
-                        upstream,
-                        dep,
-                        &mut existing_dep,
-                        dev,
-                        use_registry,
-                    )?;
+                    if dev {
+                        rewrite_workspace_dep(
+                            workspace_path,
+                            root_manifest,
+                            dep,
+                            &mut existing_dep,
+                        )?;
+                    } else {
+                        workspace_deps.push(dep.clone());
+                    }
                     manifest.insert_into_table(
                         &table,
                         &existing_dep,
@@ -148,12 +203,17 @@ pub fn rewrite_deps(
                 let mut new_ver = if let Some(v) = &dep.version {
                     v.to_string()
                 } else {
+                    // crates left out by `plan --exclude-unchanged` keep their published version
                     plan.crates
                         .iter()
                         .find(|c| c.name == existing_dep.name.as_str())
+                        .map(|c| c.to.clone())
+                        .or_else(|| {
+                            workspace_crates
+                                .get(existing_dep.name.as_str())
+                                .map(|c| c.version().to_string())
+                        })
                         .context("cant find package ".to_string() + existing_dep.name.as_str())?
-                        .to
-                        .clone()
                 };
                 if !Version::parse(&new_ver).unwrap().pre.is_empty() {
                     new_ver = format!("={}", new_ver);
@@ -161,13 +221,8 @@ pub fn rewrite_deps(
 
                 if let Some(pkg) = workspace_crates.get(existing_dep.name.as_str()) {
                     let ver = VersionReq::parse(&new_ver).unwrap();
-                    if pkg.publish().is_none()
-                        && use_registry
-                        && upstream
-                            .get(existing_dep.name.as_str())
-                            .and_then(|d| d.iter().find(|d| ver.matches(d.as_summary().version())))
-                            .is_some()
-                    {
+                    if dep_source.use_registry(pkg, upstream, &ver, dev) {
+                        // only the source changes, default-features, features and optional stay
                         let source = RegistrySource::new(&new_ver);
                         let existing_dep = existing_dep.set_source(source);
                         manifest.insert_into_table(
@@ -182,7 +237,10 @@ pub fn rewrite_deps(
                         let path = workspace_path.canonicalize()?.join(path);
                         let mut source = PathSource::new(&path);
 
-                        if dev {
+                        // cargo publish drops path dev dependencies without a version, with one
+                        // the dependency has to be published first
+                        if dev && !(version_dev_deps && !depends_on(workspace_crates, pkg, &member))
+                        {
                             existing_dep = existing_dep.clear_version();
                         } else {
                             source = source.set_version(&new_ver);
@@ -211,27 +269,93 @@ pub fn rewrite_deps(
         }
     }
 
-    Ok(())
+    Ok(workspace_deps)
+}
+
+/// Whether `pkg` depends on the member `name`, directly or through other members and counting
+/// dev dependencies.
+pub fn depends_on(workspace_crates: &BTreeMap<&str, &Package>, pkg: &Package, name: &str) -> bool {
+    let mut seen = BTreeSet::new();
+    let mut queue = vec![pkg];
+
+    while let Some(c) = queue.pop() {
+        for dep in c.dependencies() {
+            let dep = dep.package_name().as_str();
+            if dep == name {
+                return true;
+            }
+            if seen.insert(dep) {
+                queue.extend(workspace_crates.get(dep).copied());
+            }
+        }
+    }
+
+    false
+}
+
+/// Manifests being edited, each read the first time it's needed and only written by
+/// [`Manifests::write`], so edits that cascade through the workspace can be previewed.
+#[derive(Default)]
+pub struct Manifests {
+    manifests: BTreeMap<PathBuf, (String, LocalManifest)>,
+}
+
+impl Manifests {
+    pub fn get(&mut self, path: &Path) -> Result<&mut LocalManifest> {
+        if !self.manifests.contains_key(path) {
+            let manifest = LocalManifest::try_new(path)?;
+            let original = manifest.manifest.to_string();
+            self.manifests
+                .insert(path.to_path_buf(), (original, manifest));
+        }
+        Ok(&mut self.manifests.get_mut(path).unwrap().1)
+    }
+
+    /// The path, original contents and edited contents of every manifest that was changed.
+    pub fn changes(&self) -> impl Iterator<Item = (&Path, &str, String)> {
+        self.manifests
+            .iter()
+            .map(|(path, (original, manifest))| {
+                (
+                    path.as_path(),
+                    original.as_str(),
+                    manifest.manifest.to_string(),
+                )
+            })
+            .filter(|(_, original, new)| original != new)
+    }
+
+    pub fn write(&self) -> Result<()> {
+        for (path, _, new) in self.changes() {
+            std::fs::write(path, new)?;
+        }
+        Ok(())
+    }
 }
 
 pub fn remove_dep(
     workspace: &Workspace,
     root_manifest: &mut DocumentMut,
-    manifest: &mut LocalManifest,
+    manifests: &mut Manifests,
+    path: &Path,
     dep: &RemoveDep,
 ) -> Result<()> {
-    remove_dep_inner(workspace, root_manifest, manifest, dep)?;
+    remove_dep_inner(workspace, root_manifest, manifests, path, dep)?;
     Ok(())
 }
 
 pub fn remove_dep_inner(
     workspace: &Workspace,
     root_manifest: &mut DocumentMut,
-    manifest: &mut LocalManifest,
+    manifests: &mut Manifests,
+    path: &Path,
     dep: &RemoveDep,
 ) -> Result<()> {
     let mut removed = Vec::new();
+    let mut required = false;
 
+    let manifest = manifests.get(path)?;
+    let package_name = manifest.package_name()?.to_string();
     let exiting_deps = manifest
         .get_dependency_versions(&dep.name, workspace, &Features::default())
         .collect::<Vec<_>>();
@@ -243,10 +367,7 @@ pub fn remove_dep_inner(
             .collect::<Vec<_>>();
         if let Ok(dep) = dep {
             if !dep.optional.unwrap_or(false) {
-                let remove = RemoveCrate {
-                    name: manifest.package_name()?.to_string(),
-                };
-                remove_crate_inner(workspace, root_manifest, &remove)?;
+                required = true;
             } else {
                 manifest.remove_from_table(&table, dep.toml_key())?;
                 removed.push(dep.toml_key().to_string());
@@ -254,10 +375,13 @@ pub fn remove_dep_inner(
         }
     }
 
-    manifest.write()?;
+    if required {
+        let remove = RemoveCrate { name: package_name };
+        remove_crate_inner(workspace, root_manifest, manifests, &remove)?;
+    }
 
     for dep in removed {
-        remove_features_of_dep(workspace, root_manifest, manifest, &dep)?;
+        remove_features_of_dep(workspace, root_manifest, manifests, path, &dep)?;
     }
 
     Ok(())
@@ -266,10 +390,12 @@ pub fn remove_dep_inner(
 pub fn remove_features_of_dep(
     workspace: &Workspace,
     root_manifest: &mut DocumentMut,
-    manifest: &mut LocalManifest,
+    manifests: &mut Manifests,
+    path: &Path,
     toml_key: &str,
 ) -> Result<()> {
     let mut remove = Vec::new();
+    let manifest = manifests.get(path)?;
     let package_name = manifest.package_name()?.to_string();
     let features = manifest.manifest.get_table_mut(&["features".to_string()]);
     if let Ok(features) = features {
@@ -328,29 +454,28 @@ pub fn remove_features_of_dep(
 
     remove.dedup();
 
-    let features = manifest.manifest.get_table_mut(&["features".to_string()]);
-    if let Ok(features) = features {
-        let features = features.as_table_mut().context("not a table")?;
-        for key in remove {
-            remove_dep_feature_all(workspace, root_manifest, &package_name, &key)?;
-            features.remove(&key);
+    for key in remove {
+        remove_dep_feature_all(workspace, root_manifest, manifests, &package_name, &key)?;
+        let manifest = manifests.get(path)?;
+        if let Ok(features) = manifest.manifest.get_table_mut(&["features".to_string()]) {
+            features.as_table_mut().context("not a table")?.remove(&key);
         }
     }
 
-    manifest.write()?;
-
     Ok(())
 }
 
 pub fn remove_dep_feature_all(
     workspace: &Workspace,
     root_manifest: &mut DocumentMut,
+    manifests: &mut Manifests,
     name: &str,
     value: &str,
 ) -> Result<()> {
     for c in workspace.members() {
         let mut remove = Vec::new();
-        let mut manifest = LocalManifest::try_new(c.manifest_path())?;
+        let mut required = false;
+        let manifest = manifests.get(c.manifest_path())?;
 
         for (table, dep) in manifest.get_dependency_versions(name, workspace, &Features::default())
         {
@@ -361,13 +486,7 @@ pub fn remove_dep_feature_all(
             let dep = dep?;
             if let Some(features) = &dep.features {
                 if features.contains(value) {
-                    remove_crate_inner(
-                        workspace,
-                        root_manifest,
-                        &RemoveCrate {
-                            name: c.name().to_string(),
-                        },
-                    )?;
+                    required = true;
                 }
             }
         }
@@ -391,16 +510,32 @@ pub fn remove_dep_feature_all(
             features.remove(key);
         }
 
-        manifest.write()?;
+        if required {
+            remove_crate_inner(
+                workspace,
+                root_manifest,
+                manifests,
+                &RemoveCrate {
+                    name: c.name().to_string(),
+                },
+            )?;
+        }
 
         for key in remove {
-            remove_dep_feature_all(workspace, root_manifest, c.name().as_str(), &key)?;
+            remove_dep_feature_all(workspace, root_manifest, manifests, c.name().as_str(), &key)?;
         }
     }
     Ok(())
 }
 
-pub fn remove_feature(manifest: &mut LocalManifest, remove_feature: &RemoveFeature) -> Result<()> {
+pub fn remove_feature(
+    workspace: &Workspace,
+    root_manifest: &mut DocumentMut,
+    manifests: &mut Manifests,
+    path: &Path,
+    remove_feature: &RemoveFeature,
+) -> Result<()> {
+    let manifest = manifests.get(path)?;
     let features = manifest.manifest.get_table_mut(&["features".to_string()])?;
     let features = features.as_table_mut().context("not a table")?;
 
@@ -413,11 +548,149 @@ pub fn remove_feature(manifest: &mut LocalManifest, remove_feature: &RemoveFeatu
         }
     } else {
         features.remove(&remove_feature.feature);
+        for (_, needs) in features.iter_mut() {
+            let needs = needs.as_array_mut().context("not an array")?;
+            needs.retain(|need| need.as_str() != Some(remove_feature.feature.as_str()));
+        }
+
+        let name = manifest.package_name()?.to_string();
+        remove_feature_references(
+            workspace,
+            root_manifest,
+            manifests,
+            &name,
+            &remove_feature.feature,
+        )?;
     }
 
     Ok(())
 }
 
+/// Removes references to a feature of a workspace crate that has been removed from the other
+/// members, both `dep/feature` values in their features and the features enabled on the
+/// dependency, including target specific dependencies and `[workspace.dependencies]`.
+fn remove_feature_references(
+    workspace: &Workspace,
+    root_manifest: &mut DocumentMut,
+    manifests: &mut Manifests,
+    name: &str,
+    feature: &str,
+) -> Result<()> {
+    if let Some(deps) = root_manifest
+        .get_mut("workspace")
+        .and_then(|w| w.get_mut("dependencies"))
+        .and_then(|d| d.as_table_like_mut())
+    {
+        for (key, dep) in deps.iter_mut() {
+            let Some(dep) = dep.as_table_like_mut() else {
+                continue;
+            };
+            let package = dep.get("package").and_then(|p| p.as_str());
+            if package.unwrap_or(key.get()) != name {
+                continue;
+            }
+            if let Some(features) = dep.get_mut("features").and_then(|f| f.as_array_mut()) {
+                features.retain(|f| f.as_str() != Some(feature));
+            }
+        }
+    }
+
+    for c in workspace.members() {
+        if c.name().as_str() == name {
+            continue;
+        }
+
+        let keys = c
+            .dependencies()
+            .iter()
+            .filter(|d| d.package_name().as_str() == name)
+            .map(|d| d.name_in_toml().to_string())
+            .collect::<BTreeSet<_>>();
+        if keys.is_empty() {
+            continue;
+        }
+
+        let manifest = manifests.get(c.manifest_path())?;
+
+        if let Ok(features) = manifest.manifest.get_table_mut(&["features".to_string()]) {
+            let features = features.as_table_mut().context("not a table")?;
+            for (_, needs) in features.iter_mut() {
+                let needs = needs.as_array_mut().context("not an array")?;
+                needs.retain(|need| {
+                    let Some(need) = need.as_str() else {
+                        return true;
+                    };
+                    !matches!(FeatureValue::new(need.into()), FeatureValue::DepFeature { dep_name, dep_feature, .. }
+                        if keys.contains(dep_name.as_str()) && dep_feature.as_str() == feature)
+                });
+            }
+        }
+
+        for key in &keys {
+            let tables = manifest
+                .get_dependency_versions(key, workspace, &Features::default())
+                .map(|(table, _)| {
+                    table
+                        .to_table()
+                        .iter()
+                        .map(|s| s.to_string())
+                        .collect::<Vec<_>>()
+                })
+                .collect::<Vec<_>>();
+            for table in tables {
+                let Some(features) = manifest
+                    .manifest
+                    .get_table_mut(&table)?
+                    .get_mut(key)
+                    .and_then(|d| d.as_table_like_mut())
+                    .and_then(|d| d.get_mut("features"))
+                    .and_then(|f| f.as_array_mut())
+                else {
+                    continue;
+                };
+                features.retain(|f| f.as_str() != Some(feature));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Removes a value from every feature that lists it, dropping features that end up empty along
+/// with any references to them.
+pub fn remove_feature_value(
+    manifest: &mut LocalManifest,
+    remove_value: &RemoveFeatureValue,
+) -> Result<()> {
+    let Ok(features) = manifest.manifest.get_table_mut(&["features".to_string()]) else {
+        return Ok(());
+    };
+    let features = features.as_table_mut().context("not a table")?;
+    let mut remove = vec![remove_value.value.clone()];
+
+    while let Some(value) = remove.pop() {
+        let mut emptied = Vec::new();
+
+        for (key, needs) in features.iter_mut() {
+            let needs = needs.as_array_mut().context("not an array")?;
+            let len = needs.len();
+            needs.retain(|need| need.as_str() != Some(value.as_str()));
+            if needs.len() != len && needs.is_empty() {
+                emptied.push(key.get().to_string());
+            }
+        }
+
+        for feature in emptied {
+            features.remove(&feature);
+            remove.push(feature);
+        }
+    }
+
+    Ok(())
+}
+
+/// Appends the plan's description (`plan --description`) to every member's README.md under a
+/// `## Release` heading, replacing the section left by a previous apply.
 pub fn set_readme_desc(w: &Workspace, plan: &Planner) -> Result<()> {
     let Some(desc) = plan.options.description.as_ref() else {
         return Ok(());
@@ -478,18 +751,10 @@ pub fn set_version(manifest: &mut LocalManifest, new_ver: &str) -> Result<()> {
     Ok(())
 }
 
-pub fn remove_crate(workspace: &Workspace, remove_c: &RemoveCrate) -> Result<()> {
-    let root_manifest = read_to_string(workspace.root_manifest())?;
-    let mut root_manifest: DocumentMut = root_manifest.parse()?;
-    remove_crate_inner(workspace, &mut root_manifest, remove_c)?;
-    let root_manifest = root_manifest.to_string();
-    std::fs::write(workspace.root_manifest(), &root_manifest)?;
-    Ok(())
-}
-
 pub fn remove_crate_inner(
     workspace: &Workspace,
     manifest: &mut DocumentMut,
+    manifests: &mut Manifests,
     remove_c: &RemoveCrate,
 ) -> Result<()> {
     let path = workspace
@@ -508,13 +773,14 @@ pub fn remove_crate_inner(
         }
     }
 
-    remove_dep_all(&workspace, manifest, &remove_c.name)?;
+    remove_dep_all(workspace, manifest, manifests, &remove_c.name)?;
     Ok(())
 }
 
 pub fn remove_dep_all(
     workspace: &Workspace,
     root_manifest: &mut DocumentMut,
+    manifests: &mut Manifests,
     remove_c: &str,
 ) -> Result<()> {
     for c in workspace.members() {
@@ -522,11 +788,11 @@ pub fn remove_dep_all(
             .iter()
             .any(|d| d.package_name() == remove_c)
         {
-            let mut manifest = LocalManifest::try_new(c.manifest_path())?;
             remove_dep_inner(
                 workspace,
                 root_manifest,
-                &mut manifest,
+                manifests,
+                c.manifest_path(),
                 &RemoveDep {
                     name: remove_c.to_string(),
                     package: None,
@@ -536,3 +802,429 @@ pub fn remove_dep_all(
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plan::{Publish, RemoveFeature};
+    use crate::shared::{
+        test::{gctx, write_tree},
+        workspace_crates,
+    };
+
+    fn planned(workspace: &Workspace, to: &str) -> Planner {
+        Planner {
+            crates: workspace
+                .members()
+                .map(|c| Publish {
+                    name: c.name().to_string(),
+                    from: c.version().to_string(),
+                    to: to.to_string(),
+                    publish: true,
+                    verify: true,
+                    ..Default::default()
+                })
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    /// Rewrites the dependencies of `member` on `deps` the way apply does, returning the new
+    /// manifest and the workspace dependencies left to bump.
+    fn rewrite(
+        dir: &Path,
+        member: &str,
+        deps: &[&str],
+        dep_source: DepSource,
+    ) -> (String, Vec<RewriteDep>) {
+        rewrite_dev(dir, member, deps, dep_source, false)
+    }
+
+    /// Like [`rewrite`], with `version_dev_deps` like `apply --version-dev-deps`.
+    fn rewrite_dev(
+        dir: &Path,
+        member: &str,
+        deps: &[&str],
+        dep_source: DepSource,
+        version_dev_deps: bool,
+    ) -> (String, Vec<RewriteDep>) {
+        let gctx = gctx();
+        let workspace = Workspace::new(&dir.join("Cargo.toml"), &gctx).unwrap();
+        let workspace_crates = workspace_crates(&workspace).unwrap();
+        let plan = planned(&workspace, "0.2.0");
+        let root_manifest: DocumentMut = std::fs::read_to_string(workspace.root_manifest())
+            .unwrap()
+            .parse()
+            .unwrap();
+        let mut manifest = LocalManifest::try_new(&dir.join(member).join("Cargo.toml")).unwrap();
+        let deps = deps
+            .iter()
+            .map(|d| RewriteDep {
+                name: d.to_string(),
+                ..Default::default()
+            })
+            .collect::<Vec<_>>();
+
+        let workspace_deps = rewrite_deps(
+            &workspace,
+            workspace.root(),
+            &plan,
+            &root_manifest,
+            &mut manifest,
+            &workspace_crates,
+            &BTreeMap::new(),
+            &deps,
+            dep_source,
+            version_dev_deps,
+        )
+        .unwrap();
+        (manifest.manifest.to_string(), workspace_deps)
+    }
+
+    const B: &str =
+        "[package]\nname = \"b\"\nversion = \"0.1.0\"\n\n[features]\nstd = []\nx = []\n";
+
+    #[test]
+    fn registry_rewrite_keeps_dependency_options() {
+        let dir = write_tree(&[
+            ("Cargo.toml", "[workspace]\nmembers = [\"a\", \"b\"]\n"),
+            (
+                "a/Cargo.toml",
+                r#"[package]
+name = "a"
+version = "0.1.0"
+
+[dependencies]
+b = { path = "../b", version = "0.1.0", default-features = false, features = ["x"], optional = true }
+"#,
+            ),
+            ("a/src/lib.rs", "#![no_std]\n"),
+            ("b/Cargo.toml", B),
+            ("b/src/lib.rs", ""),
+        ]);
+
+        let (manifest, _) = rewrite(dir.path(), "a", &["b"], DepSource::RegistryOnly);
+        assert!(manifest.contains(
+            r#"b = { version = "0.2.0", default-features = false, features = ["x"], optional = true }"#
+        ));
+    }
+
+    #[test]
+    fn workspace_dev_dep_merges_features() {
+        let dir = write_tree(&[
+            (
+                "Cargo.toml",
+                r#"[workspace]
+members = ["a", "b"]
+
+[workspace.dependencies]
+b = { path = "b", version = "0.1.0", default-features = false, features = ["x"] }
+"#,
+            ),
+            (
+                "a/Cargo.toml",
+                r#"[package]
+name = "a"
+version = "0.1.0"
+
+[dev-dependencies]
+b = { workspace = true, features = ["std"] }
+"#,
+            ),
+            ("a/src/lib.rs", ""),
+            ("b/Cargo.toml", B),
+            ("b/src/lib.rs", ""),
+        ]);
+
+        let (manifest, workspace_deps) = rewrite(dir.path(), "a", &["b"], DepSource::Path);
+        assert!(workspace_deps.is_empty());
+        let manifest: DocumentMut = manifest.parse().unwrap();
+        let b = manifest["dev-dependencies"]["b"].as_inline_table().unwrap();
+        assert!(b.get("workspace").is_none());
+        assert_eq!(b.get("path").unwrap().as_str(), Some("../b"));
+        assert_eq!(b.get("default-features").unwrap().as_bool(), Some(false));
+        let features = b
+            .get("features")
+            .unwrap()
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|f| f.as_str().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(features, ["std", "x"]);
+    }
+
+    const DEV_DEPENDS_ON_B: &str = r#"[package]
+name = "a"
+version = "0.1.0"
+
+[dev-dependencies]
+b = { path = "../b" }
+"#;
+
+    #[test]
+    fn path_dev_dep_version_is_cleared() {
+        let dir = write_tree(&[
+            ("Cargo.toml", "[workspace]\nmembers = [\"a\", \"b\"]\n"),
+            ("a/Cargo.toml", DEV_DEPENDS_ON_B),
+            ("a/src/lib.rs", ""),
+            ("b/Cargo.toml", B),
+            ("b/src/lib.rs", ""),
+        ]);
+
+        let (manifest, _) = rewrite(dir.path(), "a", &["b"], DepSource::Path);
+        let manifest: DocumentMut = manifest.parse().unwrap();
+        let b = manifest["dev-dependencies"]["b"].as_inline_table().unwrap();
+        assert_eq!(b.get("path").unwrap().as_str(), Some("../b"));
+        assert!(b.get("version").is_none());
+    }
+
+    #[test]
+    fn path_dev_dep_gets_planned_version() {
+        let dir = write_tree(&[
+            ("Cargo.toml", "[workspace]\nmembers = [\"a\", \"b\"]\n"),
+            ("a/Cargo.toml", DEV_DEPENDS_ON_B),
+            ("a/src/lib.rs", ""),
+            ("b/Cargo.toml", B),
+            ("b/src/lib.rs", ""),
+        ]);
+
+        let (manifest, _) = rewrite_dev(dir.path(), "a", &["b"], DepSource::Path, true);
+        let manifest: DocumentMut = manifest.parse().unwrap();
+        let b = manifest["dev-dependencies"]["b"].as_inline_table().unwrap();
+        assert_eq!(b.get("path").unwrap().as_str(), Some("../b"));
+        assert_eq!(b.get("version").unwrap().as_str(), Some("0.2.0"));
+    }
+
+    #[test]
+    fn path_dev_dep_cycle_is_cleared() {
+        let dir = write_tree(&[
+            ("Cargo.toml", "[workspace]\nmembers = [\"a\", \"b\", \"c\"]\n"),
+            ("a/Cargo.toml", DEV_DEPENDS_ON_B),
+            ("a/src/lib.rs", ""),
+            (
+                "b/Cargo.toml",
+                "[package]\nname = \"b\"\nversion = \"0.1.0\"\n\n[dependencies]\nc = { path = \"../c\" }\n",
+            ),
+            ("b/src/lib.rs", ""),
+            (
+                "c/Cargo.toml",
+                "[package]\nname = \"c\"\nversion = \"0.1.0\"\n\n[dependencies]\na = { path = \"../a\" }\n",
+            ),
+            ("c/src/lib.rs", ""),
+        ]);
+
+        let (manifest, _) = rewrite_dev(dir.path(), "a", &["b"], DepSource::Path, true);
+        let manifest: DocumentMut = manifest.parse().unwrap();
+        let b = manifest["dev-dependencies"]["b"].as_inline_table().unwrap();
+        assert!(b.get("version").is_none());
+    }
+
+    #[test]
+    fn removed_feature_references_are_removed() {
+        let dir = write_tree(&[
+            (
+                "Cargo.toml",
+                r#"[workspace]
+members = ["a", "b", "c", "d"]
+
+[workspace.dependencies]
+a = { path = "a", features = ["std", "x"] }
+"#,
+            ),
+            (
+                "a/Cargo.toml",
+                "[package]\nname = \"a\"\nversion = \"0.1.0\"\n\n[features]\nstd = []\nx = []\nall = [\"std\", \"x\"]\n",
+            ),
+            ("a/src/lib.rs", ""),
+            (
+                "b/Cargo.toml",
+                r#"[package]
+name = "b"
+version = "0.1.0"
+
+[dependencies]
+a = { path = "../a", optional = true }
+
+[features]
+x = ["a?/x", "a/std"]
+"#,
+            ),
+            ("b/src/lib.rs", ""),
+            (
+                "c/Cargo.toml",
+                r#"[package]
+name = "c"
+version = "0.1.0"
+
+[target.'cfg(unix)'.dependencies]
+renamed = { package = "a", path = "../a", features = ["x"] }
+"#,
+            ),
+            ("c/src/lib.rs", ""),
+            (
+                "d/Cargo.toml",
+                "[package]\nname = \"d\"\nversion = \"0.1.0\"\n\n[dependencies]\na = { workspace = true }\n",
+            ),
+            ("d/src/lib.rs", ""),
+        ]);
+        let gctx = gctx();
+        let workspace = Workspace::new(&dir.path().join("Cargo.toml"), &gctx).unwrap();
+        let mut root_manifest: DocumentMut = std::fs::read_to_string(workspace.root_manifest())
+            .unwrap()
+            .parse()
+            .unwrap();
+        let mut manifests = Manifests::default();
+
+        remove_feature(
+            &workspace,
+            &mut root_manifest,
+            &mut manifests,
+            &dir.path().join("a/Cargo.toml"),
+            &RemoveFeature {
+                feature: "x".to_string(),
+                value: None,
+            },
+        )
+        .unwrap();
+
+        let changed = manifests
+            .changes()
+            .map(|(path, _, new)| (path.strip_prefix(dir.path()).unwrap().to_path_buf(), new))
+            .collect::<BTreeMap<_, _>>();
+        assert!(changed[Path::new("a/Cargo.toml")].contains("all = [\"std\"]"));
+        assert!(changed[Path::new("b/Cargo.toml")].contains("\"a/std\"]"));
+        assert!(!changed[Path::new("b/Cargo.toml")].contains("a?/x"));
+        assert!(changed[Path::new("c/Cargo.toml")].contains("features = []"));
+        assert!(!changed.contains_key(Path::new("d/Cargo.toml")));
+        assert!(root_manifest
+            .to_string()
+            .contains(r#"a = { path = "a", features = ["std"] }"#));
+    }
+
+    #[test]
+    fn readme_release_section() {
+        let dir = write_tree(&[
+            ("Cargo.toml", "[workspace]\nmembers = [\"a\", \"b\"]\n"),
+            (
+                "a/Cargo.toml",
+                "[package]\nname = \"a\"\nversion = \"0.1.0\"\n",
+            ),
+            ("a/src/lib.rs", ""),
+            (
+                "a/README.md",
+                "# a\n\nDoes things.\n\n## Release\n\nold release\n",
+            ),
+            ("b/Cargo.toml", B),
+            ("b/src/lib.rs", ""),
+        ]);
+        let gctx = gctx();
+        let workspace = Workspace::new(&dir.path().join("Cargo.toml"), &gctx).unwrap();
+        let readme = |c: &str| std::fs::read_to_string(dir.path().join(c).join("README.md"));
+
+        set_readme_desc(&workspace, &Planner::default()).unwrap();
+        assert!(readme("b").is_err());
+
+        let mut plan = Planner::default();
+        plan.options.description = Some("polkadot stable2409".to_string());
+        set_readme_desc(&workspace, &plan).unwrap();
+        set_readme_desc(&workspace, &plan).unwrap();
+
+        assert_eq!(
+            readme("a").unwrap(),
+            "# a\n\nDoes things.\n\n## Release\n\npolkadot stable2409\n"
+        );
+        assert_eq!(
+            readme("b").unwrap(),
+            "\n\n## Release\n\npolkadot stable2409\n"
+        );
+    }
+
+    #[test]
+    fn shared_workspace_dep_is_bumped_once() {
+        let member = |name: &str| {
+            format!(
+                "[package]\nname = \"{}\"\nversion = \"0.1.0\"\n\n[dependencies]\nd = {{ workspace = true }}\n",
+                name
+            )
+        };
+        let (a, b, c) = (member("a"), member("b"), member("c"));
+        let dir = write_tree(&[
+            (
+                "Cargo.toml",
+                r#"[workspace]
+members = ["a", "b", "c", "d"]
+
+[workspace.dependencies]
+d = { path = "d", version = "0.1.0" }
+"#,
+            ),
+            ("a/Cargo.toml", &a),
+            ("a/src/lib.rs", ""),
+            ("b/Cargo.toml", &b),
+            ("b/src/lib.rs", ""),
+            ("c/Cargo.toml", &c),
+            ("c/src/lib.rs", ""),
+            (
+                "d/Cargo.toml",
+                "[package]\nname = \"d\"\nversion = \"0.1.0\"\n",
+            ),
+            ("d/src/lib.rs", ""),
+        ]);
+
+        let mut workspace_deps = BTreeMap::new();
+        for member in ["a", "b", "c"] {
+            let (manifest, deps) = rewrite(dir.path(), member, &["d"], DepSource::Path);
+            // the member keeps inheriting it, only the root entry changes
+            let manifest: DocumentMut = manifest.parse().unwrap();
+            assert_eq!(
+                manifest["dependencies"]["d"]["workspace"].as_bool(),
+                Some(true)
+            );
+            workspace_deps.extend(deps.into_iter().map(|dep| (dep.name.clone(), dep)));
+        }
+        assert_eq!(workspace_deps.keys().collect::<Vec<_>>(), ["d"]);
+
+        let gctx = gctx();
+        let workspace = Workspace::new(&dir.path().join("Cargo.toml"), &gctx).unwrap();
+        let workspace_crates = workspace_crates(&workspace).unwrap();
+        let mut root_manifest: DocumentMut = std::fs::read_to_string(workspace.root_manifest())
+            .unwrap()
+            .parse()
+            .unwrap();
+        bump_workspace_dependency(
+            workspace.root(),
+            &planned(&workspace, "0.2.0"),
+            &mut root_manifest,
+            &workspace_crates,
+            &BTreeMap::new(),
+            &workspace_deps["d"],
+            DepSource::Path,
+        )
+        .unwrap();
+        assert!(root_manifest
+            .to_string()
+            .contains(r#"d = { path = "d", version = "0.2.0" }"#));
+    }
+
+    #[test]
+    fn path_only_member_dep() {
+        let dir = write_tree(&[
+            ("Cargo.toml", "[workspace]\nmembers = [\"a\", \"b\"]\n"),
+            (
+                "a/Cargo.toml",
+                "[package]\nname = \"a\"\nversion = \"0.1.0\"\n\n[dependencies]\nb = { path = \"../b\" }\n",
+            ),
+            ("a/src/lib.rs", ""),
+            ("b/Cargo.toml", B),
+            ("b/src/lib.rs", ""),
+        ]);
+
+        // publishing from the registry drops the path for the planned version
+        let (manifest, _) = rewrite(dir.path(), "a", &["b"], DepSource::RegistryOnly);
+        assert!(manifest.contains("b = \"0.2.0\""));
+
+        let (manifest, _) = rewrite(dir.path(), "a", &["b"], DepSource::Path);
+        assert!(manifest.contains(r#"b = { version = "0.2.0", path = "../b" }"#));
+    }
+}
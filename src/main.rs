@@ -32,6 +32,8 @@ async fn main() -> Result<()> {
         simple_logger::init()?;
     }
 
+    shared::set_git_timeout(args.git_timeout);
+
     debug!("{}-v{}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
 
     match cli.comamnd {
@@ -39,7 +41,7 @@ async fn main() -> Result<()> {
         cli::Command::Claim(claim) => claim::handle_claim(args, claim).await,
         cli::Command::Changed(changed) => changed::handle_changed(args, changed).await,
         cli::Command::Prdoc(prdoc) => prdoc::handle_prdoc(args, prdoc),
-        cli::Command::Semver(semver) => public_api::handle_public_api(args, semver),
+        cli::Command::Semver(semver) => public_api::handle_public_api(args, semver).await,
         cli::Command::Plan(plan) => plan::handle_plan(args, plan).await,
         cli::Command::Apply(apply) => apply::handle_apply(args, apply).await,
         cli::Command::Check(check) => check::handle_check(args, check).await,
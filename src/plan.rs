@@ -1,25 +1,26 @@
 use std::{
     collections::{BTreeMap, BTreeSet},
-    env::{args, current_dir},
+    env::args,
     fmt::Display,
     io::Write,
     path::{Path, PathBuf},
 };
 
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
 use cargo::{
     core::{dependency::DepKind, Package, Workspace},
     sources::IndexSummary,
     util::cache_lock::CacheLockMode,
 };
 use semver::{Prerelease, Version};
+use termcolor::{Color, ColorSpec, WriteColor};
 use toml_edit::DocumentMut;
 
 use crate::{
     changed::{self, Change},
     check,
-    cli::{Args, Check, Plan},
-    prdoc, registry,
+    cli::{Args, Check, GraphFormat, IssueKind, Plan},
+    config, prdoc, registry,
     shared::*,
 };
 
@@ -69,11 +70,37 @@ pub enum PublishReason {
     Changed,
     #[serde(rename = "--all was specified")]
     All,
+    #[serde(rename = "changed but bump was capped by --max-bump")]
+    Capped,
+    #[serde(rename = "never published")]
+    New,
+}
+
+impl Display for PublishReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PublishReason::Bumped => f.write_str("bumped by --patch"),
+            PublishReason::Specified => f.write_str("manually specified"),
+            PublishReason::Changed => f.write_str("changed"),
+            PublishReason::All => f.write_str("--all was specified"),
+            PublishReason::Capped => f.write_str("changed but bump was capped by --max-bump"),
+            PublishReason::New => f.write_str("never published"),
+        }
+    }
 }
 
 #[derive(serde::Serialize, serde::Deserialize, Default, Clone, Debug)]
 pub struct Options {
     pub description: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub commit: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub since: Option<String>,
+    #[serde(skip_serializing_if = "is_default")]
+    #[serde(default)]
+    pub exclude_unchanged: bool,
 }
 
 #[derive(serde::Serialize, serde::Deserialize, Default, Clone)]
@@ -88,6 +115,9 @@ pub struct Planner {
     #[serde(default)]
     #[serde(rename = "remove_crate")]
     pub remove_crates: Vec<RemoveCrate>,
+    /// Warnings printed while planning, `--strict` refuses to write the plan if there are any.
+    #[serde(skip)]
+    pub warnings: usize,
 }
 
 #[derive(serde::Serialize, serde::Deserialize, Default, Clone, Debug)]
@@ -101,6 +131,9 @@ pub struct Publish {
     #[serde(skip_serializing_if = "Option::is_none")]
     #[serde(default)]
     pub reason: Option<PublishReason>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub set: Option<String>,
     #[serde(default = "bool_true")]
     #[serde(skip_serializing_if = "is_not_default")]
     pub publish: bool,
@@ -143,27 +176,53 @@ pub struct RemoveFeature {
     pub value: Option<String>,
 }
 
+#[derive(serde::Serialize, serde::Deserialize, Default, Clone, Debug)]
+pub struct RemoveFeatureValue {
+    pub value: String,
+}
+
 #[derive(serde::Serialize, serde::Deserialize, Default, Eq, PartialEq, Clone, Debug)]
 pub struct RemoveCrate {
     pub name: String,
 }
 
+fn warn(args: &Args, warnings: &mut usize, msg: impl Display) -> Result<()> {
+    let mut stderr = args.stderr();
+    stderr.set_color(ColorSpec::new().set_fg(Some(Color::Yellow)).set_bold(true))?;
+    write!(stderr, "warning: ")?;
+    stderr.set_color(&ColorSpec::new())?;
+    writeln!(stderr, "{}", msg)?;
+    *warnings += 1;
+    Ok(())
+}
+
 pub async fn handle_plan(args: Args, mut plan: Plan) -> Result<()> {
-    read_stdin(&mut plan.crates)?;
+    read_stdin(&mut [&mut plan.crates])?;
 
     let config = cargo::GlobalContext::default()?;
     config.shell().set_verbosity(cargo::core::Verbosity::Quiet);
-    let path = current_dir()?;
-    let workspace = Workspace::new(&path.join("Cargo.toml"), &config)?;
+    let workspace = Workspace::new(&args.manifest_path()?, &config)?;
     let mut stdout = args.stdout();
-    let mut stderr = args.stderr();
 
-    let upstream = get_upstream(&workspace, &mut stderr).await?;
+    plan.output = workspace.root().join(&plan.output);
+    if let Some(since) = &mut plan.since {
+        *since = changed::resolve_base(workspace.root(), since)?;
+    }
+    if let Some(file) = &plan.crates_file {
+        read_crates_file(&workspace.root().join(file), &mut plan.crates)?;
+    }
+
+    if let Some(format) = plan.graph_format {
+        return print_graph(&args, &workspace, format);
+    }
 
-    let workspace_crates = workspace
-        .members()
-        .map(|m| (m.name().as_str(), m))
-        .collect::<BTreeMap<_, _>>();
+    if plan.summary {
+        return print_summary(&args, &plan);
+    }
+
+    let upstream = get_upstream(&args, &workspace).await?;
+
+    let workspace_crates = workspace_crates(&workspace)?;
 
     let mut planner = generate_plan(&args, &plan, &workspace, &workspace_crates, &upstream).await?;
 
@@ -176,26 +235,72 @@ pub async fn handle_plan(args: Args, mut plan: Plan) -> Result<()> {
 
     if plan.patch {
         patch_bump(&args, &plan, &mut planner)?;
-        write_plan(&workspace, &planner)?;
+        write_plan(&plan, &workspace, &planner, &upstream)?;
         return Ok(());
     }
 
-    write_plan(&workspace, &planner)?;
+    if let Some(set) = &plan.set {
+        assign_set(&args, &plan, &mut planner, set)?;
+        write_plan(&plan, &workspace, &planner, &upstream)?;
+        return Ok(());
+    }
+
+    // with --strict the plan is only written once the bumps below had a chance to warn
+    let bumping = plan.all || plan.since.is_some() || plan.prdoc.is_some();
+    if !plan.strict || !bumping {
+        write_plan(&plan, &workspace, &planner, &upstream)?;
+    }
+
+    if plan.all {
+        let changed = members(&args, &workspace)
+            .filter(|c| c.publish().is_none())
+            .map(|c| Change {
+                name: c.name().to_string(),
+                path: c
+                    .root()
+                    .strip_prefix(workspace.root())
+                    .unwrap()
+                    .to_path_buf(),
+                kind: changed::ChangeKind::Files,
+                bump: BumpKind::Patch,
+            })
+            .collect::<Vec<_>>();
+        args.progress(format_args!("{} packages to republish", changed.len()))?;
+        apply_bump(
+            &args,
+            &plan,
+            &mut planner,
+            &upstream,
+            &changed,
+            PublishReason::All,
+        )?;
+        write_plan(&plan, &workspace, &planner, &upstream)?;
+        return Ok(());
+    }
 
     if let Some(from) = &plan.since {
-        let changed = changed::get_changed_crates(&workspace, true, from, "HEAD")?;
+        let mut changed = changed::get_changed_crates(&workspace, true, from, "HEAD")?;
+        if plan.propagate_all {
+            changed::find_all_indirect_changes(&workspace, &mut changed);
+        }
         let indirect = changed
             .iter()
             .filter(|c| matches!(c.kind, changed::ChangeKind::Dependency))
             .count();
-        writeln!(
-            stderr,
+        args.progress(format_args!(
             "{} packages changed {} indirect",
             changed.len(),
             indirect
+        ))?;
+        apply_bump(
+            &args,
+            &plan,
+            &mut planner,
+            &upstream,
+            &changed,
+            PublishReason::Changed,
         )?;
-        apply_bump(&plan, &mut planner, &upstream, &changed)?;
-        write_plan(&workspace, &planner)?;
+        write_plan(&plan, &workspace, &planner, &upstream)?;
         return Ok(());
     }
 
@@ -210,20 +315,29 @@ pub async fn handle_plan(args: Args, mut plan: Plan) -> Result<()> {
         });
 
         changed.retain(|c| c.bump != BumpKind::None);
+        if plan.propagate_all {
+            changed::find_all_indirect_changes(&workspace, &mut changed);
+        }
 
         let indirect = changed
             .iter()
             .filter(|c| matches!(c.kind, changed::ChangeKind::Dependency))
             .filter(|c| c.bump != BumpKind::None)
             .count();
-        writeln!(
-            stderr,
+        args.progress(format_args!(
             "{} packages changed {} indirect",
             changed.len(),
             indirect
+        ))?;
+        apply_bump(
+            &args,
+            &plan,
+            &mut planner,
+            &upstream,
+            &changed,
+            PublishReason::Changed,
         )?;
-        apply_bump(&plan, &mut planner, &upstream, &changed)?;
-        write_plan(&workspace, &planner)?;
+        write_plan(&plan, &workspace, &planner, &upstream)?;
         return Ok(());
     }
 
@@ -231,30 +345,38 @@ pub async fn handle_plan(args: Args, mut plan: Plan) -> Result<()> {
 }
 
 pub async fn get_upstream(
+    args: &Args,
     workspace: &Workspace<'_>,
-    stderr: &mut termcolor::StandardStream,
 ) -> Result<BTreeMap<String, Vec<IndexSummary>>> {
     let mut upstream = BTreeMap::new();
     let _lock = workspace
         .gctx()
         .acquire_package_cache_lock(CacheLockMode::DownloadExclusive)?;
     let mut reg = registry::get_registry(workspace)?;
-    writeln!(stderr, "looking up crates...",)?;
+    args.progress("looking up crates...")?;
     registry::download_crates(&mut reg, workspace, true)?;
-    for c in workspace.members().filter(|c| c.publish().is_none()) {
+    for c in members(args, workspace).filter(|c| c.publish().is_none()) {
         let idx_summaries = registry::get_crate(&mut reg, c.name());
         // New crates (not published yet) should be handled gracefully as
         // a summary can not be fetched for them from the registry.
-        if let Ok(summary) = idx_summaries {
-            upstream.insert(c.name().to_string(), summary);
+        match idx_summaries {
+            Ok(summary) => {
+                upstream.insert(c.name().to_string(), summary);
+            }
+            Err(e) if registry::is_not_found(&e) => (),
+            Err(e) => return Err(e),
         }
 
         for dep in c.dependencies() {
             if dep.source_id().is_git() || dep.source_id().is_path() {
                 // Similarly, the same should happen for new crates that represent dependencies
                 // of member crates.
-                if let Ok(package) = registry::get_crate(&mut reg, dep.package_name()) {
-                    upstream.insert(dep.package_name().to_string(), package);
+                match registry::get_crate(&mut reg, dep.package_name()) {
+                    Ok(package) => {
+                        upstream.insert(dep.package_name().to_string(), package);
+                    }
+                    Err(e) if registry::is_not_found(&e) => (),
+                    Err(e) => return Err(e),
                 }
             }
         }
@@ -263,11 +385,16 @@ pub async fn get_upstream(
 }
 
 pub fn apply_bump(
+    args: &Args,
     plan: &Plan,
     planner: &mut Planner,
     upstream: &BTreeMap<String, Vec<IndexSummary>>,
     changes: &[Change],
+    reason: PublishReason,
 ) -> Result<()> {
+    let mut stderr = args.stderr();
+    let mut capped = 0;
+
     for change in changes {
         let Some(c) = planner.crates.iter_mut().find(|c| c.name == change.name) else {
             continue;
@@ -282,14 +409,49 @@ pub fn apply_bump(
         let mut to = Version::parse(&c.from)?;
         c.to = to.to_string();
         c.bump = change.bump;
-        c.reason = Some(PublishReason::Changed);
-        let u = upstream.get(c.name.as_str()).unwrap_or(&empty);
+        c.reason = Some(reason.clone());
+
+        // crates added since the last release get their first release at their current version
+        let new = !upstream.get(c.name.as_str()).is_some_and(|u| !u.is_empty());
+        if new {
+            c.bump = BumpKind::None;
+            c.reason = Some(PublishReason::New);
+        }
 
-        match change.bump {
+        if let Some(max_bump) = plan.max_bump.filter(|_| !new) {
+            if change.bump > max_bump {
+                warn(
+                    args,
+                    &mut planner.warnings,
+                    format_args!(
+                        "{} needs a {} bump but is capped to {} by --max-bump",
+                        c.name, change.bump, max_bump
+                    ),
+                )?;
+                c.bump = max_bump;
+                c.reason = Some(PublishReason::Capped);
+                capped += 1;
+            }
+        }
+
+        if plan.hold_version {
+            continue;
+        }
+
+        // yanked versions still count as taken unless we're reclaiming them
+        let u = upstream
+            .get(c.name.as_str())
+            .unwrap_or(&empty)
+            .iter()
+            .filter(|u| !plan.reclaim_yanked || !u.is_yanked())
+            .map(|u| u.as_summary().version())
+            .collect::<Vec<_>>();
+
+        match c.bump {
             BumpKind::None => (),
             BumpKind::Patch => loop {
                 to.patch += 1;
-                if !u.iter().any(|u| u.as_summary().version() == &to) {
+                if !u.contains(&&to) {
                     break;
                 }
             },
@@ -300,7 +462,7 @@ pub fn apply_bump(
                     to.minor += 1;
                     to.patch = 0;
                 }
-                if !u.iter().any(|u| u.as_summary().version() == &to) {
+                if !u.contains(&&to) {
                     break;
                 }
             },
@@ -308,17 +470,14 @@ pub fn apply_bump(
                 if to.major == 0 {
                     to.minor += 1;
                     to.patch = 0;
-                    if !u.iter().any(|u| {
-                        u.as_summary().version().major == 0
-                            && u.as_summary().version().minor == to.minor
-                    }) {
+                    if !u.iter().any(|u| u.major == 0 && u.minor == to.minor) {
                         break;
                     }
                 } else {
                     to.major += 1;
                     to.minor = 0;
                     to.patch = 0;
-                    if !u.iter().any(|u| u.as_summary().version().major == to.major) {
+                    if !u.iter().any(|u| u.major == to.major) {
                         break;
                     }
                 }
@@ -335,6 +494,14 @@ pub fn apply_bump(
         c.to = to.to_string();
     }
 
+    if capped > 0 {
+        writeln!(
+            stderr,
+            "{} packages had their bump capped by --max-bump and need review",
+            capped
+        )?;
+    }
+
     Ok(())
 }
 
@@ -366,6 +533,25 @@ pub fn patch_bump(args: &Args, plan: &Plan, planner: &mut Planner) -> Result<()>
     Ok(())
 }
 
+fn assign_set(args: &Args, plan: &Plan, planner: &mut Planner, set: &str) -> Result<()> {
+    for package in &plan.crates {
+        let c = planner
+            .crates
+            .iter_mut()
+            .find(|c| c.name == *package)
+            .with_context(|| format!("could not find crate '{}' in Plan.toml", package))?;
+        c.set = Some(set.to_string());
+    }
+
+    let len = planner
+        .crates
+        .iter()
+        .filter(|c| c.set.as_deref() == Some(set))
+        .count();
+    args.progress(format_args!("{} packages in set '{}'", len, set))?;
+    Ok(())
+}
+
 pub async fn generate_plan(
     args: &Args,
     plan: &Plan,
@@ -383,20 +569,46 @@ pub async fn generate_plan(
     if plan.description.is_some() {
         planner.options.description = plan.description.clone();
     }
+    if plan.since.is_some() {
+        planner.options.since = plan.since.clone();
+    }
+    if plan.exclude_unchanged {
+        planner.options.exclude_unchanged = true;
+    }
+    planner.options.commit = changed::head_commit(workspace.root()).ok();
 
     if !plan.skip_check {
-        check::check(
+        let ret = check::check(args, plan_check(), Some(upstream)).await?;
+        if ret != 0 {
+            warn(
+                args,
+                &mut planner.warnings,
+                "check found problems that will make apply fail",
+            )?;
+        }
+    }
+
+    let unpublishable = check::needs_publish(args, workspace, false);
+    if !unpublishable.is_empty() {
+        let list = unpublishable
+            .iter()
+            .map(|(c, deps)| {
+                format!(
+                    "{} (needed by {})",
+                    c,
+                    deps.iter().copied().collect::<Vec<_>>().join(", ")
+                )
+            })
+            .collect::<Vec<_>>();
+
+        warn(
             args,
-            Check {
-                allow_nonfatal: true,
-                allow_unpublished: false,
-                no_check_owner: false,
-                recursive: false,
-                quiet: false,
-                paths: 0,
-            },
-        )
-        .await?;
+            &mut planner.warnings,
+            format_args!(
+                "'publish = false' is set on dependencies of published crates, apply will fail:\n    {}",
+                list.join("\n    ")
+            ),
+        )?;
     }
 
     let order = order(args, &workspace)?;
@@ -406,7 +618,25 @@ pub async fn generate_plan(
         let c = *workspace_crates.get(c).unwrap();
 
         if let Some(old_crate) = old_crate {
-            planner.crates.push(old_crate.clone());
+            let mut old_crate = old_crate.clone();
+
+            if plan.refresh_from {
+                let from = get_version(plan, upstream, c)?;
+                old_crate.from = from.to_string();
+
+                if Version::parse(&old_crate.to).is_ok_and(|to| from > to) {
+                    warn(
+                        args,
+                        &mut planner.warnings,
+                        format_args!(
+                            "{} is now {} upstream which is newer than the planned {}",
+                            old_crate.name, from, old_crate.to
+                        ),
+                    )?;
+                }
+            }
+
+            planner.crates.push(old_crate);
             continue;
         }
 
@@ -419,6 +649,7 @@ pub async fn generate_plan(
             to: from.to_string(),
             bump: BumpKind::None,
             reason: None,
+            set: None,
             rewrite_dep: vec![],
             remove_feature: vec![],
             remove_dep: vec![],
@@ -426,8 +657,11 @@ pub async fn generate_plan(
         });
     }
 
+    check_published_versions(args, &mut planner, upstream)?;
+
     let mut expanded = planner.clone();
     expand_plan(&workspace, workspace_crates, &mut expanded, upstream).await?;
+    check_removed_crates(workspace, &expanded)?;
 
     if old_plan.crates.is_empty() {
         writeln!(
@@ -461,6 +695,24 @@ pub async fn generate_plan(
     Ok(planner)
 }
 
+/// The check plan runs before planning. New crates are always unpublished when a plan is made,
+/// so they mustn't count as a warning for `--strict`.
+fn plan_check() -> Check {
+    Check {
+        allow_nonfatal: true,
+        allow_unpublished: true,
+        no_check_owner: false,
+        offline: false,
+        recursive: false,
+        msrv_consistent: false,
+        quiet: false,
+        paths: 0,
+        allow: vec![IssueKind::Unpublished],
+        deny: Vec::new(),
+        cache: Default::default(),
+    }
+}
+
 pub async fn expand_plan(
     w: &Workspace<'_>,
     workspace_crates: &BTreeMap<&str, &Package>,
@@ -494,9 +746,240 @@ pub async fn expand_plan(
             pkg.publish = c.publish().is_none();
         }
     }
+
+    // catch rewrites apply can't resolve before it starts writing manifests
+    let mut missing = Vec::new();
+    for pkg in &planner.crates {
+        let Some(c) = workspace_crates.get(pkg.name.as_str()) else {
+            continue;
+        };
+
+        for dep in pkg.rewrite_dep.iter().filter(|d| d.version.is_none()) {
+            let name = c
+                .dependencies()
+                .iter()
+                .find(|d| d.name_in_toml().as_str() == dep.name)
+                .map(|d| d.package_name().as_str())
+                .unwrap_or(&dep.name);
+            if !planner.crates.iter().any(|c| c.name == name)
+                && !workspace_crates.get(name).is_some_and(|c| {
+                    upstream
+                        .get(name)
+                        .is_some_and(|u| registry::has_version(u, c.version()))
+                })
+            {
+                missing.push(format!("{} -> {}", pkg.name, name));
+            }
+        }
+    }
+    if !missing.is_empty() {
+        bail!(
+            "rewrite_dep targets have no version and are not in the plan: {}",
+            missing.join(", ")
+        );
+    }
+
     Ok(())
 }
 
+/// Make sure applying the plan won't try to lower the version of any crate or publish a version
+/// that is already published.
+///
+/// `allow_published` only warns about published versions instead, apply skips them when it's
+/// run again after a partial publish.
+pub fn validate_plan(
+    args: &Args,
+    planner: &Planner,
+    upstream: &BTreeMap<String, Vec<IndexSummary>>,
+    allow_published: bool,
+) -> Result<()> {
+    let mut stderr = args.stderr();
+    let mut lowered = Vec::new();
+    let mut published = Vec::new();
+
+    for c in planner.crates.iter().filter(|c| c.publish) {
+        let from = Version::parse(&c.from)
+            .with_context(|| format!("invalid from version for {}", c.name))?;
+        let to =
+            Version::parse(&c.to).with_context(|| format!("invalid to version for {}", c.name))?;
+
+        if to < from {
+            lowered.push(format!("{} ({} -> {})", c.name, from, to));
+        } else if to != from
+            && upstream
+                .get(&c.name)
+                .is_some_and(|u| registry::has_version(u, &to))
+        {
+            published.push(format!("{}@{}", c.name, to));
+        }
+    }
+
+    if !lowered.is_empty() {
+        bail!(
+            "plan would lower the version of {} packages:\n    {}",
+            lowered.len(),
+            lowered.join("\n    ")
+        );
+    }
+
+    if !published.is_empty() && !allow_published {
+        bail!(
+            "{} packages are already published, pass --allow-published to skip them:\n    {}",
+            published.len(),
+            published.join("\n    ")
+        );
+    }
+
+    if !published.is_empty() {
+        stderr.set_color(ColorSpec::new().set_fg(Some(Color::Yellow)).set_bold(true))?;
+        write!(stderr, "warning: ")?;
+        stderr.set_color(&ColorSpec::new())?;
+        writeln!(
+            stderr,
+            "{} packages are already published and will be skipped: {}",
+            published.len(),
+            published.join(", ")
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Warns about crates planned to be bumped to a version that is already published, usually
+/// because the plan is older than the latest release.
+fn check_published_versions(
+    args: &Args,
+    planner: &mut Planner,
+    upstream: &BTreeMap<String, Vec<IndexSummary>>,
+) -> Result<()> {
+    let taken = planner
+        .crates
+        .iter()
+        .filter(|c| c.publish && c.to != c.from)
+        .filter(|c| {
+            Version::parse(&c.to).is_ok_and(|to| {
+                upstream
+                    .get(&c.name)
+                    .is_some_and(|u| registry::has_version(u, &to))
+            })
+        })
+        .map(|c| format!("{}@{}", c.name, c.to))
+        .collect::<Vec<_>>();
+
+    if taken.is_empty() {
+        return Ok(());
+    }
+
+    warn(
+        args,
+        &mut planner.warnings,
+        format_args!(
+            "planned versions are already published, apply will refuse these crates unless they are re-bumped or --allow-published is passed:\n    {}",
+            taken.join("\n    ")
+        ),
+    )
+}
+
+/// Make sure no crate that is still published depends unconditionally on a crate that the plan
+/// or Plan.config removes.
+///
+/// Removing a crate also removes every member that requires it, the same way apply cascades the
+/// removal, so only dependents that apply can't take out of the workspace are left behind. Those
+/// are members matched by a glob in `workspace.members`, which would be published without the
+/// dependency.
+fn check_removed_crates(workspace: &Workspace, planner: &Planner) -> Result<()> {
+    let config = config::read_config(workspace.root())?;
+    let listed = listed_members(workspace)?;
+    let mut removed = planner
+        .remove_crates
+        .iter()
+        .chain(&config.remove_crates)
+        .map(|r| r.name.clone())
+        .collect::<BTreeSet<_>>();
+
+    loop {
+        let cascade = workspace
+            .members()
+            .filter(|c| !removed.contains(c.name().as_str()))
+            .filter(|c| listed.iter().any(|l| workspace.root().join(l) == c.root()))
+            .filter(|c| {
+                c.dependencies()
+                    .iter()
+                    .any(|d| !d.is_optional() && removed.contains(d.package_name().as_str()))
+            })
+            .map(|c| c.name().to_string())
+            .collect::<Vec<_>>();
+
+        if cascade.is_empty() {
+            break;
+        }
+        removed.extend(cascade);
+    }
+
+    let mut orphaned = Vec::new();
+
+    for c in workspace
+        .members()
+        .filter(|c| c.publish().is_none() && !removed.contains(c.name().as_str()))
+    {
+        let pkg = planner.crates.iter().find(|p| p.name == c.name().as_str());
+        if pkg.is_some_and(|p| !p.publish) {
+            continue;
+        }
+
+        let deps = c
+            .dependencies()
+            .iter()
+            .filter(|d| {
+                d.kind() != DepKind::Development
+                    && !d.is_optional()
+                    && removed.contains(d.package_name().as_str())
+                    && !pkg.is_some_and(|p| {
+                        p.rewrite_dep
+                            .iter()
+                            .any(|r| r.name == d.name_in_toml().as_str())
+                    })
+            })
+            .map(|d| d.package_name().to_string())
+            .collect::<BTreeSet<_>>();
+
+        if !deps.is_empty() {
+            orphaned.push(format!(
+                "{} (needs {})",
+                c.name(),
+                deps.into_iter().collect::<Vec<_>>().join(", ")
+            ));
+        }
+    }
+
+    if !orphaned.is_empty() {
+        bail!(
+            "published crates depend unconditionally on removed crates:\n    {}",
+            orphaned.join("\n    ")
+        );
+    }
+
+    Ok(())
+}
+
+/// The paths listed in `workspace.members` of the root manifest, which are the only members apply
+/// can drop from the workspace.
+fn listed_members(workspace: &Workspace) -> Result<Vec<PathBuf>> {
+    let root_manifest: DocumentMut = std::fs::read_to_string(workspace.root_manifest())?.parse()?;
+    let members = root_manifest
+        .get("workspace")
+        .and_then(|w| w.get("members"))
+        .and_then(|m| m.as_array())
+        .map(|m| {
+            m.iter()
+                .filter_map(|m| m.as_str())
+                .map(PathBuf::from)
+                .collect()
+        })
+        .unwrap_or_default();
+    Ok(members)
+}
+
 fn get_version(
     plan: &Plan,
     upstream: &BTreeMap<String, Vec<IndexSummary>>,
@@ -504,7 +987,7 @@ fn get_version(
 ) -> Result<Version> {
     let upstreamc = upstream.get(c.name().as_str());
     let mut from = upstreamc
-        .and_then(|u| max_ver(u, plan.pre.is_some()))
+        .and_then(|u| registry::latest_release(u, plan.pre.is_some()))
         .map(|u| u.as_summary().version().clone())
         .unwrap_or_else(|| {
             let mut v = c.version().clone();
@@ -580,7 +1063,7 @@ async fn rewrite_git_deps(
             if !workspace_crates.contains_key(dep.package_name().as_str()) {
                 let version = upstream
                     .get(dep.package_name().as_str())
-                    .and_then(|c| max_ver(c, false))
+                    .and_then(|c| registry::latest_release(c, false))
                     .with_context(|| {
                         format!("crate {} has no crates.io release", dep.package_name())
                     })?
@@ -599,15 +1082,126 @@ async fn rewrite_git_deps(
     Ok(rewrite)
 }
 
-fn order<'a>(args: &Args, workspace: &'a Workspace) -> Result<Vec<&'a str>> {
+/// Prints the crates the plan publishes along with their versions and why they're published.
+fn print_summary(args: &Args, plan: &Plan) -> Result<()> {
+    let mut stdout = args.stdout();
     let mut stderr = args.stderr();
-    writeln!(stderr, "calculating order...")?;
+
+    let planner = read_plan(plan)?.with_context(|| {
+        format!(
+            "Can't find {}. Have your ran plan first?",
+            plan.output.display()
+        )
+    })?;
+
+    writeln!(
+        stderr,
+        "{:<50}{:<16}{:<16}{:<8}{:<0}",
+        "Crate", "From", "To", "Bump", "Reason"
+    )?;
+
+    let mut n = 0;
+    for c in planner
+        .crates
+        .iter()
+        .filter(|c| c.publish && (c.from != c.to || c.reason.is_some()))
+    {
+        let reason = c
+            .reason
+            .as_ref()
+            .map_or_else(|| "-".to_string(), |r| r.to_string());
+        writeln!(
+            stdout,
+            "{:<50}{:<16}{:<16}{:<8}{:<0}",
+            c.name,
+            c.from,
+            c.to,
+            c.bump.to_string(),
+            reason
+        )?;
+        n += 1;
+    }
+
+    writeln!(
+        stderr,
+        "{} of {} packages to publish",
+        n,
+        planner.crates.len()
+    )?;
+    Ok(())
+}
+
+/// Prints the non dev dependency edges between workspace members.
+fn print_graph(args: &Args, workspace: &Workspace, format: GraphFormat) -> Result<()> {
+    let mut stdout = args.stdout();
+    let order = order(args, workspace)?;
+    let ids = order
+        .iter()
+        .enumerate()
+        .map(|(i, name)| (*name, i))
+        .collect::<BTreeMap<_, _>>();
+
+    match format {
+        GraphFormat::Dot => writeln!(stdout, "digraph {{")?,
+        GraphFormat::Mermaid => writeln!(stdout, "graph TD")?,
+    }
+
+    for name in &order {
+        if format == GraphFormat::Dot {
+            writeln!(stdout, "    \"{}\";", name)?;
+        } else {
+            writeln!(stdout, "    n{}[\"{}\"]", ids[name], name)?;
+        }
+    }
+
+    for (name, deps) in dep_graph(args, workspace) {
+        for dep in deps {
+            if format == GraphFormat::Dot {
+                writeln!(stdout, "    \"{}\" -> \"{}\";", name, dep)?;
+            } else {
+                writeln!(stdout, "    n{} --> n{}", ids[name], ids[dep])?;
+            }
+        }
+    }
+
+    if format == GraphFormat::Dot {
+        writeln!(stdout, "}}")?;
+    }
+
+    Ok(())
+}
+
+/// Maps each workspace member to the members it has non dev dependencies on.
+pub fn dep_graph<'a>(
+    args: &'a Args,
+    workspace: &'a Workspace,
+) -> BTreeMap<&'a str, BTreeSet<&'a str>> {
+    let names = members(args, workspace)
+        .map(|c| c.name().as_str())
+        .collect::<BTreeSet<_>>();
+
+    members(args, workspace)
+        .map(|member| {
+            let deps = member
+                .dependencies()
+                .iter()
+                .filter(|d| d.kind() != DepKind::Development)
+                .map(|d| d.package_name().as_str())
+                .filter(|d| names.contains(d))
+                .collect();
+            (member.name().as_str(), deps)
+        })
+        .collect()
+}
+
+fn order<'a>(args: &Args, workspace: &'a Workspace) -> Result<Vec<&'a str>> {
+    args.progress("calculating order...")?;
 
     let mut deps = BTreeMap::new();
     let mut order = Vec::new();
 
     // map name to deps
-    for member in workspace.members() {
+    for member in members(args, workspace) {
         let deps_list = member
             .dependencies()
             .iter()
@@ -616,8 +1210,7 @@ fn order<'a>(args: &Args, workspace: &'a Workspace) -> Result<Vec<&'a str>> {
         deps.insert(member.name().as_str(), deps_list);
     }
 
-    let mut names = workspace
-        .members()
+    let mut names = members(args, workspace)
         .map(|c| c.name())
         .collect::<BTreeSet<_>>();
 
@@ -627,6 +1220,7 @@ fn order<'a>(args: &Args, workspace: &'a Workspace) -> Result<Vec<&'a str>> {
             deps.retain(|dep| names.contains(dep.package_name().as_str()))
         }
 
+        let remaining = deps.len();
         deps.retain(|name, deps| {
             if deps.is_empty() {
                 order.push(*name);
@@ -636,13 +1230,37 @@ fn order<'a>(args: &Args, workspace: &'a Workspace) -> Result<Vec<&'a str>> {
                 true
             }
         });
+
+        if deps.len() == remaining {
+            bail!(
+                "can't order {} because of a dependency cycle, see `workspace --cycles`",
+                deps.keys().copied().collect::<Vec<_>>().join(", ")
+            );
+        }
     }
 
     Ok(order)
 }
 
+/// Adds the crate names listed in `path`, one per line, to `crates`.
+///
+/// Blank lines and lines starting with `#` are ignored.
+fn read_crates_file(path: &Path, crates: &mut Vec<String>) -> Result<()> {
+    let list = std::fs::read_to_string(path)
+        .with_context(|| format!("failed to read {}", path.display()))?;
+
+    for name in list.lines().map(str::trim) {
+        if name.is_empty() || name.starts_with('#') || crates.iter().any(|c| c == name) {
+            continue;
+        }
+        crates.push(name.to_string());
+    }
+
+    Ok(())
+}
+
 fn read_plan(plan: &Plan) -> Result<Option<Planner>> {
-    let path = Path::new("Plan.toml");
+    let path = &plan.output;
 
     if plan.new {
         return Ok(None);
@@ -692,17 +1310,54 @@ fn plan_to_str(workspace: &Workspace, planner: &Planner) -> Result<String> {
     Ok(output)
 }
 
-fn write_plan(workspace: &Workspace, planner: &Planner) -> Result<()> {
-    let output = plan_to_str(workspace, planner)?;
-    std::fs::write(Path::new("Plan.toml"), output)?;
+fn write_plan(
+    plan: &Plan,
+    workspace: &Workspace,
+    planner: &Planner,
+    upstream: &BTreeMap<String, Vec<IndexSummary>>,
+) -> Result<()> {
+    if plan.strict && planner.warnings > 0 {
+        bail!(
+            "{} warnings while planning, not writing {} because of --strict",
+            planner.warnings,
+            plan.output.display()
+        );
+    }
+
+    let output = if planner.options.exclude_unchanged {
+        let mut planner = planner.clone();
+        planner
+            .crates
+            .retain(|c| !is_unchanged(workspace, upstream, c));
+        plan_to_str(workspace, &planner)?
+    } else {
+        plan_to_str(workspace, planner)?
+    };
+    std::fs::write(&plan.output, output)?;
     Ok(())
 }
 
-fn max_ver(crates: &[IndexSummary], pre: bool) -> Option<&IndexSummary> {
-    crates
-        .iter()
-        .filter(|c| pre || c.as_summary().version().pre.is_empty())
-        .max_by_key(|c| c.as_summary().version())
+/// Whether a crate can be left out of the plan as apply has nothing to do for it.
+///
+/// Only crates whose manifest version is already published are, so crates that depend on them can
+/// still be rewritten to that version.
+fn is_unchanged(
+    workspace: &Workspace,
+    upstream: &BTreeMap<String, Vec<IndexSummary>>,
+    c: &Publish,
+) -> bool {
+    c.reason.is_none()
+        && c.from == c.to
+        && c.rewrite_dep.is_empty()
+        && c.remove_dep.is_empty()
+        && c.remove_feature.is_empty()
+        && workspace.members().any(|m| {
+            m.name().as_str() == c.name
+                && m.version().to_string() == c.to
+                && upstream
+                    .get(&c.name)
+                    .is_some_and(|u| registry::has_version(u, m.version()))
+        })
 }
 
 fn rewrite_deps(
@@ -730,3 +1385,182 @@ fn rewrite_deps(
 
     Ok(rewrite)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::registry::test::summary;
+    use crate::shared::test::{commit_all, gctx, write_tree};
+    use clap::Parser;
+
+    const GIT_DEP: &str = r#"
+[package]
+name = "a"
+version = "0.1.0"
+
+[dependencies]
+g = { git = "https://github.com/paritytech/g" }
+"#;
+
+    const DEPENDS_ON_A: &str = r#"
+[package]
+name = "b"
+version = "0.1.0"
+
+[dependencies]
+a = { path = "../../a", version = "0.1.0" }
+"#;
+
+    fn removes_git_dep() -> Planner {
+        Planner {
+            remove_crates: vec![RemoveCrate {
+                name: "g".to_string(),
+            }],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn removed_git_dep_cascades_to_dependents() {
+        let dir = write_tree(&[
+            ("Cargo.toml", "[workspace]\nmembers = [\"a\", \"c/b\"]\n"),
+            ("a/Cargo.toml", GIT_DEP),
+            ("a/src/lib.rs", ""),
+            ("c/b/Cargo.toml", DEPENDS_ON_A),
+            ("c/b/src/lib.rs", ""),
+        ]);
+        let gctx = gctx();
+        let workspace = Workspace::new(&dir.path().join("Cargo.toml"), &gctx).unwrap();
+
+        check_removed_crates(&workspace, &removes_git_dep()).unwrap();
+    }
+
+    #[test]
+    fn strict_allows_new_crates() {
+        assert!(!check::fails(IssueKind::Unpublished, &plan_check()));
+    }
+
+    #[test]
+    fn strict_refuses_to_write_plan_with_warnings() {
+        let dir = write_tree(&[
+            ("Cargo.toml", "[workspace]\nmembers = [\"a\"]\n"),
+            (
+                "a/Cargo.toml",
+                "[package]\nname = \"a\"\nversion = \"0.1.0\"\n",
+            ),
+            ("a/src/lib.rs", ""),
+        ]);
+        let gctx = gctx();
+        let workspace = Workspace::new(&dir.path().join("Cargo.toml"), &gctx).unwrap();
+        let args = Args::parse_from(["parity-publish", "--no-progress"]);
+        let output = dir.path().join("Plan.toml");
+        let plan = Plan::parse_from(["plan", "--strict", "--output", output.to_str().unwrap()]);
+        let upstream = BTreeMap::from([("a".to_string(), vec![summary("a", "0.2.0")])]);
+        let mut planner = Planner {
+            crates: vec![Publish {
+                name: "a".to_string(),
+                from: "0.1.0".to_string(),
+                to: "0.2.0".to_string(),
+                publish: true,
+                verify: true,
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+
+        write_plan(&plan, &workspace, &planner, &upstream).unwrap();
+        std::fs::remove_file(&output).unwrap();
+
+        check_published_versions(&args, &mut planner, &upstream).unwrap();
+        assert_eq!(planner.warnings, 1);
+        let err = write_plan(&plan, &workspace, &planner, &upstream).unwrap_err();
+        assert!(err.to_string().contains("--strict"), "{}", err);
+        assert!(!output.exists());
+    }
+
+    #[test]
+    fn removed_crate_orphans_globbed_member() {
+        let dir = write_tree(&[
+            ("Cargo.toml", "[workspace]\nmembers = [\"a\", \"c/*\"]\n"),
+            ("a/Cargo.toml", GIT_DEP),
+            ("a/src/lib.rs", ""),
+            ("c/b/Cargo.toml", DEPENDS_ON_A),
+            ("c/b/src/lib.rs", ""),
+        ]);
+        let gctx = gctx();
+        let workspace = Workspace::new(&dir.path().join("Cargo.toml"), &gctx).unwrap();
+
+        let err = check_removed_crates(&workspace, &removes_git_dep()).unwrap_err();
+        assert!(err.to_string().contains("b (needs a)"), "{}", err);
+    }
+
+    #[tokio::test]
+    async fn since_plans_members_added_after_the_ref() {
+        let dir = write_tree(&[
+            ("Cargo.toml", "[workspace]\nmembers = [\"a\"]\n"),
+            (
+                "a/Cargo.toml",
+                "[package]\nname = \"a\"\nversion = \"0.1.0\"\n",
+            ),
+            ("a/src/lib.rs", ""),
+        ]);
+        let base = commit_all(dir.path());
+        std::fs::write(
+            dir.path().join("Plan.toml"),
+            "[[crate]]\nname = \"a\"\nfrom = \"0.1.0\"\nto = \"0.1.0\"\n",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.path().join("Cargo.toml"),
+            "[workspace]\nmembers = [\"a\", \"b\"]\n",
+        )
+        .unwrap();
+        std::fs::create_dir_all(dir.path().join("b/src")).unwrap();
+        std::fs::write(dir.path().join("b/src/lib.rs"), "").unwrap();
+        std::fs::write(
+            dir.path().join("b/Cargo.toml"),
+            DEPENDS_ON_A.replace("../../a", "../a"),
+        )
+        .unwrap();
+        commit_all(dir.path());
+
+        let gctx = gctx();
+        let workspace = Workspace::new(&dir.path().join("Cargo.toml"), &gctx).unwrap();
+        let workspace_crates = workspace_crates(&workspace).unwrap();
+        let args = Args::parse_from(["parity-publish", "--no-progress"]);
+        let output = dir.path().join("Plan.toml");
+        let plan = Plan::parse_from([
+            "plan",
+            "--skip-check",
+            "--since",
+            &base,
+            "--output",
+            output.to_str().unwrap(),
+        ]);
+        let upstream = BTreeMap::from([("a".to_string(), vec![summary("a", "0.1.0")])]);
+
+        let mut planner = generate_plan(&args, &plan, &workspace, &workspace_crates, &upstream)
+            .await
+            .unwrap();
+        let changed = changed::get_changed_crates(&workspace, true, &base, "HEAD").unwrap();
+        apply_bump(
+            &args,
+            &plan,
+            &mut planner,
+            &upstream,
+            &changed,
+            PublishReason::Changed,
+        )
+        .unwrap();
+
+        let b = planner.crates.iter().find(|c| c.name == "b").unwrap();
+        assert!(b.publish);
+        assert_eq!(b.to, "0.1.0");
+        assert!(matches!(b.bump, BumpKind::None));
+        assert!(matches!(b.reason, Some(PublishReason::New)));
+
+        let a = planner.crates.iter().find(|c| c.name == "a").unwrap();
+        assert_eq!(a.to, "0.1.0");
+        assert!(a.reason.is_none());
+    }
+}
@@ -1,5 +1,4 @@
 use std::collections::HashMap;
-use std::env::current_dir;
 use std::fs::{read_dir, read_to_string};
 use std::io::Write;
 use std::path::{Path, PathBuf};
@@ -11,7 +10,7 @@ use semver::VersionReq;
 use termcolor::{Color, ColorSpec, WriteColor};
 use toml_edit::{Formatted, Item, Table, Value};
 
-use crate::changed::{find_indirect_changes, get_changed_crates, Change, ChangeKind};
+use crate::changed::{self, find_indirect_changes, get_changed_crates, Change, ChangeKind};
 use crate::cli::{Args, Prdoc, Semver};
 use crate::plan::BumpKind;
 use crate::public_api::{self, print_diff};
@@ -25,6 +24,64 @@ pub struct DepChange {
     pub breaking: bool,
 }
 
+#[derive(serde::Serialize)]
+struct Validation {
+    name: String,
+    path: PathBuf,
+    stated: Option<BumpKind>,
+    predicted: BumpKind,
+    exceeds_max_bump: bool,
+    reason: Option<ValidationReason>,
+    /// Why the bump was overridden, if it was
+    #[serde(skip_serializing_if = "Option::is_none")]
+    justification: Option<String>,
+    ok: bool,
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+enum ValidationReason {
+    Files,
+    Dependency,
+    Api,
+    Override,
+}
+
+#[derive(serde::Deserialize)]
+struct BumpOverrides {
+    #[serde(rename = "crate", default)]
+    crates: Vec<BumpOverride>,
+}
+
+/// A bump to validate a crate against instead of the predicted one.
+#[derive(serde::Deserialize)]
+struct BumpOverride {
+    name: String,
+    bump: BumpKind,
+    reason: String,
+}
+
+fn read_bump_overrides(path: &Path) -> Result<Vec<BumpOverride>> {
+    let overrides =
+        read_to_string(path).with_context(|| format!("failed to read {}", path.display()))?;
+    let overrides: BumpOverrides = toml::from_str(&overrides)
+        .with_context(|| format!("failed to parse {}", path.display()))?;
+
+    for (i, o) in overrides.crates.iter().enumerate() {
+        if o.reason.trim().is_empty() {
+            bail!("bump override for {} has no reason", o.name);
+        }
+        if overrides.crates[..i]
+            .iter()
+            .any(|other| other.name == o.name)
+        {
+            bail!("bump for {} is overridden more than once", o.name);
+        }
+    }
+
+    Ok(overrides.crates)
+}
+
 #[derive(serde::Deserialize)]
 struct Document {
     crates: Vec<Crates>,
@@ -46,6 +103,7 @@ pub fn get_prdocs(
 ) -> Result<Vec<Change>> {
     let mut stderr = args.stderr();
     let mut entries = HashMap::new();
+    let path = workspace.root().join(path);
 
     if !path.exists() {
         stderr.set_color(ColorSpec::new().set_fg(Some(Color::Yellow)).set_bold(true))?;
@@ -54,7 +112,7 @@ pub fn get_prdocs(
         writeln!(stderr, "no PR Doc")?;
         return Ok(Vec::new());
     } else if path.is_file() {
-        read_prdoc(path, workspace, &mut entries)?;
+        read_prdoc(&path, workspace, &mut entries)?;
     } else {
         let dirs = read_dir(path).context("failed to read prdoc dir")?;
 
@@ -114,13 +172,16 @@ fn read_prdoc(
 }
 
 pub fn handle_prdoc(args: Args, mut prdoc: Prdoc) -> Result<()> {
-    read_stdin(&mut prdoc.crates)?;
+    read_stdin(&mut [&mut prdoc.crates])?;
     let mut stdout = args.stdout();
     let config = cargo::GlobalContext::default()?;
     config.shell().set_verbosity(cargo::core::Verbosity::Quiet);
-    let path = current_dir()?.join("Cargo.toml");
+    let path = args.manifest_path()?;
     let workspace = Workspace::new(&path, &config)?;
     let deps = !prdoc.no_deps;
+    if let Some(since) = &mut prdoc.since {
+        *since = changed::resolve_base(workspace.root(), since)?;
+    }
 
     if prdoc.validate {
         return validate(&args, &prdoc, &workspace);
@@ -395,7 +456,10 @@ fn get_dep<'a>(
 }
 
 fn validate(args: &Args, prdoc: &Prdoc, w: &Workspace) -> Result<()> {
-    let mut stdout = args.stdout();
+    // with --json the human report goes to stderr so stdout is only the json
+    let json = prdoc.json;
+    let mut stdout = if json { args.stderr() } else { args.stdout() };
+    let mut results = Vec::new();
 
     let Some(from) = &prdoc.since else {
         bail!("--since must be specified for --validate");
@@ -413,12 +477,26 @@ fn validate(args: &Args, prdoc: &Prdoc, w: &Workspace) -> Result<()> {
     writeln!(stdout, "Always reason about semver changes yourself")?;
     writeln!(stdout)?;
 
-    writeln!(stdout, "validating prdocs...")?;
+    args.progress("validating prdocs...")?;
     let prdocs = get_prdocs(args, w, &prdoc.prdoc_path, false, &prdoc.crates)?;
+    let overrides = match &prdoc.bump_overrides {
+        Some(path) => read_bump_overrides(&w.root().join(path))?,
+        None => Vec::new(),
+    };
+
+    for o in &overrides {
+        if !prdocs.iter().any(|p| p.name == o.name) {
+            let mut stderr = args.stderr();
+            stderr.set_color(ColorSpec::new().set_fg(Some(Color::Yellow)).set_bold(true))?;
+            write!(stderr, "warning: ")?;
+            stderr.set_color(&ColorSpec::new())?;
+            writeln!(stderr, "bump override for {} is not in any PR Doc", o.name)?;
+        }
+    }
 
     let max_bump = prdoc.max_bump;
 
-    writeln!(stdout, "checking file changes...")?;
+    args.progress("checking file changes...")?;
     let mut changes = get_changed_crates(w, false, from, "HEAD")?;
     let mut ok = true;
 
@@ -435,18 +513,40 @@ fn validate(args: &Args, prdoc: &Prdoc, w: &Workspace) -> Result<()> {
         major: false,
         verbose: false,
         minimum_nightly_rust_version: false,
+        fail_on: None,
+        plan: PathBuf::from("Plan.toml"),
         since: Some(from.clone()),
+        since_release: false,
+        from_tag_pattern: Vec::new(),
+        to: None,
+        baseline_version: None,
         crates,
         toolchain: prdoc.toolchain.clone(),
+        jobs: prdoc.jobs,
+        dependents_of: Vec::new(),
+        rustdoc_cache: false,
+        additions_only: false,
     };
 
-    let (tmp, upstreams) = public_api::get_from_commit(&w, &breaking, from)?;
+    let (tmp, mut upstreams) = public_api::get_from_commit(&w, &breaking, from)?;
 
-    writeln!(stdout, "checking dep changes...")?;
+    args.progress("checking dep changes...")?;
     let dep_changes = manifest_deps_changed(w, tmp.path(), w.root())?;
 
+    // a crate with no file or dep changes can't have an api change so don't build it. crates that
+    // depend on a changed crate are still built as they may re-export it
+    let mut affected = changes.clone();
+    affected.extend(dep_changes.iter().map(|d| Change {
+        name: d.name.clone(),
+        path: d.path.clone(),
+        kind: ChangeKind::Dependency,
+        bump: BumpKind::Major,
+    }));
+    find_indirect_changes(w, &mut affected);
+    upstreams.retain(|u| affected.iter().any(|c| c.name == u.name().as_str()));
+
     if !prdocs.is_empty() {
-        writeln!(stdout, "checking semver changes...")?;
+        args.progress("checking semver changes...")?;
         let breaking =
             public_api::get_changes(args, w, upstreams, &breaking, &dep_changes, prdoc.verbose)?;
 
@@ -463,10 +563,43 @@ fn validate(args: &Args, prdoc: &Prdoc, w: &Workspace) -> Result<()> {
                     BumpKind::None
                 }
             });
+            let bump_override = overrides.iter().find(|o| o.name == prdoc.name);
+            let predicted = bump_override.map_or(predicted, |o| o.bump);
+            let reason = if bump_override.is_some() {
+                Some(ValidationReason::Override)
+            } else if api_change.is_some() {
+                Some(ValidationReason::Api)
+            } else if dep_changes.iter().any(|d| d.name == prdoc.name) {
+                Some(ValidationReason::Dependency)
+            } else if changed {
+                Some(ValidationReason::Files)
+            } else {
+                None
+            };
+            let exceeds_max_bump = max_bump.is_some_and(|max| prdoc.bump > max || predicted > max);
+            let mut result = Validation {
+                name: prdoc.name.clone(),
+                path: prdoc.path.clone(),
+                stated: Some(prdoc.bump),
+                predicted,
+                exceeds_max_bump,
+                reason,
+                justification: bump_override.map(|o| o.reason.clone()),
+                ok: true,
+            };
 
             if prdoc.bump == predicted
                 || (prdoc.bump == BumpKind::None && predicted == BumpKind::Patch)
             {
+                if let Some(o) = bump_override {
+                    stdout.set_color(ColorSpec::new().set_bold(true))?;
+                    write!(stdout, "{}", prdoc.name)?;
+                    stdout.set_color(ColorSpec::new().set_bold(false))?;
+                    writeln!(stdout, " ({}):", prdoc.path.display())?;
+                    writeln!(stdout, "    Bump overridden to {}: {}", o.bump, o.reason)?;
+                    writeln!(stdout)?;
+                }
+                results.push(result);
                 continue;
             }
 
@@ -478,10 +611,18 @@ fn validate(args: &Args, prdoc: &Prdoc, w: &Workspace) -> Result<()> {
             stdout.set_color(ColorSpec::new().set_bold(true))?;
             writeln!(stdout, "{}", prdoc.bump)?;
             stdout.set_color(ColorSpec::new().set_bold(false))?;
-            write!(stdout, "    Predicted semver change: ")?;
-            stdout.set_color(ColorSpec::new().set_bold(true))?;
-            writeln!(stdout, "{}", predicted)?;
-            stdout.set_color(ColorSpec::new().set_bold(false))?;
+            if let Some(o) = bump_override {
+                write!(stdout, "    Overridden semver change: ")?;
+                stdout.set_color(ColorSpec::new().set_bold(true))?;
+                writeln!(stdout, "{}", predicted)?;
+                stdout.set_color(ColorSpec::new().set_bold(false))?;
+                writeln!(stdout, "    Override reason: {}", o.reason)?;
+            } else {
+                write!(stdout, "    Predicted semver change: ")?;
+                stdout.set_color(ColorSpec::new().set_bold(true))?;
+                writeln!(stdout, "{}", predicted)?;
+                stdout.set_color(ColorSpec::new().set_bold(false))?;
+            }
 
             if let Some(max_allowed_bump) = max_bump {
                 let prdoc_bad = prdoc.bump > max_allowed_bump;
@@ -505,19 +646,23 @@ fn validate(args: &Args, prdoc: &Prdoc, w: &Workspace) -> Result<()> {
                     writeln!(stdout, "{}", max_allowed_bump)?;
                     stdout.set_color(ColorSpec::new().set_bold(false))?;
                     ok = false;
+                    result.ok = false;
                 }
             }
 
             if let Some(api_change) = api_change {
-                if api_change.bump == BumpKind::Major && prdoc.bump != BumpKind::Major {
+                // an override replaces what the api change predicts
+                let checked = bump_override.is_none();
+                if checked && api_change.bump == BumpKind::Major && prdoc.bump != BumpKind::Major {
                     writeln!(
                         stdout,
                         "    Major API change found but prdoc specified {}",
                         prdoc.bump
                     )?;
                     ok = false;
+                    result.ok = false;
                 }
-                if api_change.bump == BumpKind::Minor && prdoc.bump == BumpKind::Patch {
+                if checked && api_change.bump == BumpKind::Minor && prdoc.bump == BumpKind::Patch {
                     // just warn don't return 1 for this
                     writeln!(
                         stdout,
@@ -525,11 +670,15 @@ fn validate(args: &Args, prdoc: &Prdoc, w: &Workspace) -> Result<()> {
                         prdoc.bump
                     )?;
                     ok = false;
+                    result.ok = false;
+                }
+                if !json {
+                    print_diff(args, &api_change)?;
                 }
-                print_diff(args, &api_change)?;
             }
 
             writeln!(stdout)?;
+            results.push(result);
         }
     }
 
@@ -566,6 +715,25 @@ fn validate(args: &Args, prdoc: &Prdoc, w: &Workspace) -> Result<()> {
         }
         ok = false;
         writeln!(stdout)?;
+        results.push(Validation {
+            name: change.name.clone(),
+            path: change.path.clone(),
+            stated: None,
+            predicted: change.bump,
+            exceeds_max_bump: max_bump.is_some_and(|max| change.bump > max),
+            reason: Some(match change.kind {
+                ChangeKind::Dependency => ValidationReason::Dependency,
+                _ => ValidationReason::Files,
+            }),
+            justification: None,
+            ok: false,
+        });
+    }
+
+    if json {
+        let mut stdout = args.stdout();
+        serde_json::to_writer_pretty(&mut stdout, &results)?;
+        writeln!(stdout)?;
     }
 
     if !ok {
@@ -1,25 +1,34 @@
-use anyhow::{ensure, Result};
+use anyhow::{ensure, Context, Result};
 use cargo::{
     core::{Package, PackageSet, Workspace},
     sources::{source::SourceMap, RegistrySource},
     util::cache_lock::CacheLockMode,
-    util::VersionExt,
 };
 use cargo_semver_checks::ReleaseType;
 use log::debug;
 use public_api::{diff::PublicApiDiff, tokens::Token, PublicItem, MINIMUM_NIGHTLY_RUST_VERSION};
-use std::{collections::HashSet, env::current_dir, path::PathBuf};
+use semver::Version;
+use std::{
+    collections::{HashSet, VecDeque},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+    thread,
+};
 use std::{io::Write, process::Command};
 use tempfile::TempDir;
 use termcolor::ColorSpec;
 use termcolor::{Color, WriteColor};
 
 use crate::{
+    changed::{self, rev_parse},
     cli::{Args, Semver},
-    plan::BumpKind,
+    plan::{self, BumpKind, Planner},
     prdoc::{manifest_deps_changed, DepChange},
     registry,
-    shared::read_stdin,
+    shared::{cache_dir, git, read_stdin},
 };
 
 pub struct Change {
@@ -27,40 +36,108 @@ pub struct Change {
     pub path: PathBuf,
     pub bump: BumpKind,
     pub diff: PublicApiDiff,
+    /// Why the baseline couldn't be built, in which case a major change is assumed
+    pub baseline_error: Option<String>,
 }
 
-pub fn handle_public_api(args: Args, mut breaking: Semver) -> Result<()> {
+pub async fn handle_public_api(args: Args, mut breaking: Semver) -> Result<()> {
     if breaking.minimum_nightly_rust_version {
         println!("{}", MINIMUM_NIGHTLY_RUST_VERSION);
         return Ok(());
     }
 
-    read_stdin(&mut breaking.crates)?;
+    read_stdin(&mut [&mut breaking.crates, &mut breaking.dependents_of])?;
     let mut stdout = args.stdout();
-    let mut stderr = args.stderr();
     let config = cargo::GlobalContext::default()?;
     config.shell().set_verbosity(cargo::core::Verbosity::Quiet);
-    let path = current_dir()?.join("Cargo.toml");
+    let path = args.manifest_path()?;
     let workspace = Workspace::new(&path, &config)?;
-    let mut tmp = None;
+    let mut tmps = Vec::new();
+    if let Some(since) = &mut breaking.since {
+        *since = changed::resolve_base(workspace.root(), since)?;
+    }
+
+    if !breaking.dependents_of.is_empty() {
+        let mut changes = Vec::new();
+        for name in &breaking.dependents_of {
+            let c = workspace
+                .members()
+                .find(|c| c.name().as_str() == name)
+                .with_context(|| format!("can't find package '{}'", name))?;
+            changes.push(changed::Change {
+                name: name.clone(),
+                path: c.root().strip_prefix(workspace.root())?.to_path_buf(),
+                kind: changed::ChangeKind::Files,
+                bump: BumpKind::Major,
+            });
+        }
+        changed::find_indirect_changes(&workspace, &mut changes);
+        breaking.crates.extend(changes.into_iter().map(|c| c.name));
+    }
 
-    let upstreams = if let Some(commit) = &breaking.since {
-        let (t, upstream) = get_from_commit(&workspace, &breaking, commit)?;
-        tmp = Some(t);
+    let release_commits = if breaking.since_release {
+        let upstream = plan::get_upstream(&args, &workspace).await?;
+        changed::release_commits(&args, &workspace, &upstream, &breaking.from_tag_pattern)?
+    } else {
+        Default::default()
+    };
+
+    let root = workspace.root().to_path_buf();
+    let to_tmp;
+    let to_workspace;
+    let workspace = if let Some(to) = &breaking.to {
+        to_tmp = checkout(&workspace, to)?;
+        to_workspace = Workspace::new(&to_tmp.path().join("Cargo.toml"), &config)?;
+        &to_workspace
+    } else {
+        &workspace
+    };
+
+    let mut dep_changes = Vec::new();
+    let upstreams = if let Some(since) = &breaking.since {
+        // the checkout of --to may not have the same refs so resolve --since here
+        let commit = rev_parse(&root, since)?;
+        let (tmp, upstream) = get_from_commit(workspace, &breaking, &commit)?;
+        dep_changes = manifest_deps_changed(workspace, tmp.path(), workspace.root())?;
+        tmps.push(tmp);
         upstream
+    } else if breaking.since_release {
+        // crates released at the same commit share a checkout
+        let mut upstreams = Vec::new();
+        for (commit, names) in &release_commits {
+            let (tmp, mut upstream) = get_from_commit(workspace, &breaking, commit)?;
+            upstream.retain(|u| names.contains(u.name().as_str()));
+            let mut changes = manifest_deps_changed(workspace, tmp.path(), workspace.root())?;
+            changes.retain(|c| names.contains(&c.name));
+            dep_changes.extend(changes);
+            upstreams.extend(upstream);
+            tmps.push(tmp);
+        }
+        upstreams
     } else {
-        get_from_last_release(&args, &workspace, &breaking)?
+        get_from_last_release(&args, workspace, &breaking)?
     };
-    writeln!(stderr, "building crates...",)?;
+    args.progress("building crates...")?;
 
-    let dep_changes = if let Some(tmp) = &tmp {
-        manifest_deps_changed(&workspace, tmp.path(), workspace.root())?
+    let changes = get_changes(&args, workspace, upstreams, &breaking, &dep_changes, true)?;
+    let plan_path = root.join(&breaking.plan);
+    let planner = if breaking.fail_on.is_some() && plan_path.exists() {
+        let plan = std::fs::read_to_string(&plan_path)?;
+        Some(toml::from_str::<Planner>(&plan)?)
     } else {
-        Default::default()
+        None
     };
-    let changes = get_changes(&args, &workspace, upstreams, &breaking, &dep_changes, true)?;
+    let fail = breaking.fail_on.is_some_and(|fail_on| {
+        changes
+            .iter()
+            .any(|c| c.bump >= fail_on && !bumped_by_plan(planner.as_ref(), c))
+    });
 
     for c in changes {
+        if breaking.additions_only && c.diff.added.is_empty() {
+            continue;
+        }
+
         if breaking.paths >= 2 {
             writeln!(stdout, "{}", c.path.join("Cargo.toml").display())?;
         } else if breaking.paths == 1 {
@@ -72,7 +149,18 @@ pub fn handle_public_api(args: Args, mut breaking: Semver) -> Result<()> {
             write!(stdout, "{}", c.name)?;
             stdout.set_color(ColorSpec::new().set_bold(false))?;
             writeln!(stdout, " ({}):", c.path.display())?;
+            if breaking.additions_only {
+                print_additions(&args, &c)?;
+                writeln!(stdout)?;
+                continue;
+            }
             writeln!(stdout, "    {}", c.bump)?;
+            if c.baseline_error.is_some() {
+                writeln!(
+                    stdout,
+                    "    baseline failed to build, assuming major change"
+                )?;
+            }
             if breaking.verbose {
                 print_diff(&args, &c)?;
             }
@@ -81,37 +169,91 @@ pub fn handle_public_api(args: Args, mut breaking: Semver) -> Result<()> {
         }
     }
 
+    if fail {
+        std::process::exit(1);
+    }
+
     Ok(())
 }
 
-pub fn get_from_commit(
-    workspace: &Workspace,
-    breaking: &Semver,
-    commit: &str,
-) -> Result<(TempDir, Vec<Package>)> {
+/// The bump a plan makes going from `from` to `to`. Like when planning, a patch release of a 0.x
+/// crate covers a minor change and a minor release a major one.
+fn planned_bump(from: &Version, to: &Version) -> BumpKind {
+    if to <= from {
+        BumpKind::None
+    } else if from.major == 0 {
+        if to.major > 0 || to.minor > from.minor {
+            BumpKind::Major
+        } else {
+            BumpKind::Minor
+        }
+    } else if to.major > from.major {
+        BumpKind::Major
+    } else if to.minor > from.minor {
+        BumpKind::Minor
+    } else {
+        BumpKind::Patch
+    }
+}
+
+/// Whether the plan already publishes the crate with a big enough bump for the change.
+fn bumped_by_plan(planner: Option<&Planner>, c: &Change) -> bool {
+    planner
+        .into_iter()
+        .flat_map(|p| &p.crates)
+        .filter(|p| p.publish && p.name == c.name)
+        .any(|p| match (Version::parse(&p.from), Version::parse(&p.to)) {
+            (Ok(from), Ok(to)) => planned_bump(&from, &to) >= c.bump,
+            _ => false,
+        })
+}
+
+/// Clones the workspace's git repo into a temporary directory and checks out `commit`.
+fn checkout(workspace: &Workspace, commit: &str) -> Result<TempDir> {
     let dir = workspace.root().parent().unwrap();
     let dir = tempfile::TempDir::with_prefix_in("parity_publish-", dir)?;
     debug!("tempdir for local clone: {}", dir.path().display());
 
-    let status = Command::new("git")
-        .arg("clone")
-        .arg("-q")
-        .arg("-n")
-        .arg(workspace.root())
-        .arg(dir.path())
-        .status()?;
-    ensure!(status.success(), "git exited non 0");
-
-    let status = Command::new("git")
-        .arg("-C")
-        .arg(dir.path())
-        .arg("checkout")
-        .arg("-q")
-        .arg(commit)
-        .status()?;
-    ensure!(status.success(), "git exited non 0");
+    let res = git(
+        Command::new("git")
+            .arg("clone")
+            .arg("-q")
+            .arg("-n")
+            .arg(workspace.root())
+            .arg(dir.path()),
+        "clone",
+    )?;
+    ensure!(
+        res.status.success(),
+        "git clone failed: {}",
+        String::from_utf8_lossy(&res.stderr)
+    );
+
+    let res = git(
+        Command::new("git")
+            .arg("-C")
+            .arg(dir.path())
+            .arg("checkout")
+            .arg("-q")
+            .arg(commit),
+        "checkout",
+    )?;
+    ensure!(
+        res.status.success(),
+        "git checkout failed: {}",
+        String::from_utf8_lossy(&res.stderr)
+    );
 
     debug!("checked out {} in local clone", commit);
+    Ok(dir)
+}
+
+pub fn get_from_commit(
+    workspace: &Workspace,
+    breaking: &Semver,
+    commit: &str,
+) -> Result<(TempDir, Vec<Package>)> {
+    let dir = checkout(workspace, commit)?;
 
     let mut upstream = Vec::new();
     let uworkspace = Workspace::new(&dir.path().join("Cargo.toml"), workspace.gctx())?;
@@ -140,18 +282,16 @@ fn get_from_last_release(
     workspace: &Workspace<'_>,
     breaking: &Semver,
 ) -> Result<Vec<Package>> {
-    let mut stderr = args.stderr();
-
     let _lock = workspace
         .gctx()
         .acquire_package_cache_lock(CacheLockMode::DownloadExclusive)?;
     let mut reg = registry::get_registry(&workspace)?;
     let mut upstreams = Vec::new();
 
-    writeln!(stderr, "looking up crates...",)?;
+    args.progress("looking up crates...")?;
     registry::download_crates(&mut reg, &workspace, false)?;
 
-    writeln!(stderr, "downloading crates...",)?;
+    args.progress("downloading crates...")?;
     for c in workspace.members() {
         if c.publish().is_some() {
             continue;
@@ -164,14 +304,16 @@ fn get_from_last_release(
         }
 
         let upstream = registry::get_crate(&mut reg, c.name())?;
-        let upstream = upstream
-            .iter()
-            .filter(|c| !c.is_yanked())
-            .filter(|c| !c.as_summary().version().is_prerelease())
-            .max_by_key(|c| c.as_summary().version());
-
-        let Some(upstream) = upstream else {
-            continue;
+        let upstream = if let Some(version) = &breaking.baseline_version {
+            upstream
+                .iter()
+                .find(|u| u.as_summary().version() == version)
+                .with_context(|| format!("{} has no {} release", c.name(), version))?
+        } else {
+            let Some(upstream) = registry::latest_release(&upstream, false) else {
+                continue;
+            };
+            upstream
         };
 
         upstreams.push(upstream.clone());
@@ -200,74 +342,267 @@ fn get_from_last_release(
     Ok(upstreams)
 }
 
+/// A crate to build at HEAD and at the version it's compared against.
+struct Build<'a> {
+    name: String,
+    path: PathBuf,
+    manifest_path: PathBuf,
+    upstream_manifest_path: PathBuf,
+    upstream_version: String,
+    /// Published versions never change so their rustdoc json can be cached
+    upstream_published: bool,
+    dep_changes: Vec<&'a DepChange>,
+}
+
+struct Builder<'a> {
+    args: &'a Args,
+    breaking: &'a Semver,
+    silent: bool,
+    /// Where to cache rustdoc json of published versions
+    cache: Option<PathBuf>,
+    /// Where the json of each build is copied to before the next build can overwrite it
+    json_dir: TempDir,
+    n: AtomicUsize,
+    total: usize,
+}
+
 pub fn get_changes(
     args: &Args,
     workspace: &Workspace<'_>,
     upstreams: Vec<cargo::core::Package>,
     breaking: &Semver,
-    dep_changes: &Vec<DepChange>,
+    dep_changes: &[DepChange],
     silent: bool,
 ) -> Result<Vec<Change>> {
-    let mut changes = Vec::new();
-    let mut stdout = args.stdout();
-
-    let mut n = 0;
-    let total = workspace
+    // cargo's packages can't be shared between threads so pull out what the builds need
+    let builds = workspace
         .members()
-        .filter(|c| upstreams.iter().any(|u| c.name() == u.name()))
-        .count()
-        * 2;
-    for c in workspace.members() {
-        let Some(upstream) = upstreams.iter().find(|u| c.name() == u.name()) else {
-            continue;
-        };
+        .filter_map(|c| {
+            let upstream = upstreams.iter().find(|u| c.name() == u.name())?;
+            Some(Build {
+                name: c.name().to_string(),
+                path: c.root().strip_prefix(workspace.root()).unwrap().to_owned(),
+                manifest_path: c.manifest_path().to_owned(),
+                upstream_manifest_path: upstream.manifest_path().to_owned(),
+                upstream_version: upstream.version().to_string(),
+                upstream_published: upstream.package_id().source_id().is_registry(),
+                dep_changes: dep_changes
+                    .iter()
+                    .filter(|d| d.name == c.name().as_str())
+                    .collect(),
+            })
+        })
+        .collect::<Vec<_>>();
+
+    let jobs = match breaking.jobs {
+        0 => thread::available_parallelism().map_or(1, |n| n.get()),
+        n => n,
+    };
+    let cache = if breaking.rustdoc_cache {
+        Some(rustdoc_cache_dir(&breaking.toolchain)?)
+    } else {
+        None
+    };
+    let builder = Builder {
+        args,
+        breaking,
+        silent,
+        cache,
+        json_dir: TempDir::new()?,
+        n: AtomicUsize::new(1),
+        total: builds.len() * 2,
+    };
 
-        n += 1;
+    let queue = Mutex::new(builds.iter().enumerate().collect::<VecDeque<_>>());
+    let changes = Mutex::new(Vec::new());
+    let jobs = jobs.min(builds.len());
+    // cargo locks the target directory for the whole build so builds sharing one would still run
+    // one at a time
+    let target_dirs = (0..jobs)
+        .map(|worker| {
+            (jobs > 1).then(|| {
+                workspace
+                    .target_dir()
+                    .into_path_unlocked()
+                    .join("parity-publish")
+                    .join(format!("semver-{}", worker))
+            })
+        })
+        .collect::<Vec<_>>();
+
+    thread::scope(|s| {
+        let workers = target_dirs
+            .iter()
+            .map(|target_dir| {
+                let (queue, changes, builder) = (&queue, &changes, &builder);
+                s.spawn(move || -> Result<()> {
+                    loop {
+                        let Some((i, build)) = queue.lock().unwrap().pop_front() else {
+                            return Ok(());
+                        };
+                        if let Some(change) = builder.change(build, target_dir.as_deref())? {
+                            changes.lock().unwrap().push((i, change));
+                        }
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+
+        workers.into_iter().try_for_each(|w| w.join().unwrap())
+    })?;
+
+    // keep the output in workspace order however the builds finished
+    let mut changes = changes.into_inner().unwrap();
+    changes.sort_by_key(|(i, _)| *i);
+    let changes = changes.into_iter().map(|(_, c)| c).collect::<Vec<_>>();
+
+    let unbuildable = changes
+        .iter()
+        .filter_map(|c| Some((c, c.baseline_error.as_ref()?)))
+        .collect::<Vec<_>>();
+    if !unbuildable.is_empty() {
+        let mut stderr = args.stderr();
+        stderr.set_color(ColorSpec::new().set_fg(Some(Color::Yellow)).set_bold(true))?;
+        write!(stderr, "warning: ")?;
+        stderr.set_color(&ColorSpec::new())?;
         writeln!(
-            stdout,
-            "({:3<}/{:3<}) building {}-HEAD...",
-            n,
-            total,
-            c.name(),
+            stderr,
+            "{} baselines failed to build, assuming major changes:",
+            unbuildable.len()
         )?;
+        for (c, err) in unbuildable {
+            writeln!(stderr, "    {}: {}", c.name, err)?;
+        }
+    }
 
-        let json_path = rustdoc_json::Builder::default()
-            .toolchain(&breaking.toolchain)
-            .quiet(true)
-            .silent(silent)
-            .manifest_path(c.manifest_path())
-            .build()?;
+    Ok(changes)
+}
 
-        // Backup the file to avoid overwriting it in the next `rustdoc_json::Builder` invocation:
-        let _ = std::fs::copy(&json_path, json_path.with_extension("new"));
-        let json_path = json_path.with_extension("new");
+/// The directory rustdoc json built by `toolchain` is cached in.
+///
+/// Nightly toolchains change under the same name so the directory is named after the exact
+/// `rustc --version` rather than the toolchain.
+fn rustdoc_cache_dir(toolchain: &str) -> Result<PathBuf> {
+    let res = Command::new("rustc")
+        .arg(format!("+{}", toolchain))
+        .arg("--version")
+        .output()
+        .context("failed to run rustc")?;
+    ensure!(
+        res.status.success(),
+        "rustc +{} --version failed: {}",
+        toolchain,
+        String::from_utf8_lossy(&res.stderr).trim()
+    );
+
+    let version = String::from_utf8(res.stdout)?
+        .trim()
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '.' || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect::<String>();
+    let dir = cache_dir().join("rustdoc").join(version);
+    std::fs::create_dir_all(&dir)?;
+    Ok(dir)
+}
 
-        let new = cargo_semver_checks::Rustdoc::from_path(&json_path);
-        let new_diff = public_api::Builder::from_rustdoc_json(&json_path).build()?;
-        let mut new = cargo_semver_checks::Check::new(new);
+impl Builder<'_> {
+    fn progress(&self, name: &str, version: &str) -> Result<()> {
+        // keep progress out of the way of quiet output
+        let mut stdout = if self.breaking.quiet {
+            self.args.stderr()
+        } else {
+            self.args.stdout()
+        };
 
-        n += 1;
         writeln!(
             stdout,
             "({:3<}/{:3<}) building {}-{}...",
-            n,
-            total,
-            c.name(),
-            upstream.version(),
+            self.n.fetch_add(1, Ordering::SeqCst),
+            self.total,
+            name,
+            version,
         )?;
+        Ok(())
+    }
+
+    /// Copies the json rustdoc wrote for a crate somewhere only this run uses, as the next build
+    /// in the same target directory writes to the same path.
+    fn keep_json(&self, json_path: &Path, name: &str, kind: &str) -> Result<PathBuf> {
+        let path = self.json_dir.path().join(format!("{}.{}.json", name, kind));
+        std::fs::copy(json_path, &path)
+            .with_context(|| format!("failed to copy {}", json_path.display()))?;
+        Ok(path)
+    }
+
+    /// Builds the crate at HEAD and its baseline in `target_dir`, or cargo's default target
+    /// directory when it's `None`.
+    fn change(&self, build: &Build, target_dir: Option<&Path>) -> Result<Option<Change>> {
+        self.progress(&build.name, "HEAD")?;
 
-        let json_path = rustdoc_json::Builder::default()
-            .toolchain(&breaking.toolchain)
+        let rustdoc = || {
+            let builder = rustdoc_json::Builder::default();
+            match target_dir {
+                Some(dir) => builder.target_dir(dir),
+                None => builder,
+            }
+        };
+
+        let json_path = rustdoc()
+            .toolchain(&self.breaking.toolchain)
             .quiet(true)
-            .silent(silent)
-            .manifest_path(upstream.manifest_path())
+            .silent(self.silent)
+            .manifest_path(&build.manifest_path)
             .build()?;
 
-        // Backup the file to a known-good location:
-        let _ = std::fs::copy(&json_path, json_path.with_extension("old"));
-        let json_path = json_path.with_extension("old");
+        let json_path = self.keep_json(&json_path, &build.name, "new")?;
+
+        let new = cargo_semver_checks::Rustdoc::from_path(&json_path);
+        let new_diff = public_api::Builder::from_rustdoc_json(&json_path).build()?;
+        let mut new = cargo_semver_checks::Check::new(new);
+
+        self.progress(&build.name, &build.upstream_version)?;
+
+        let cached = self
+            .cache
+            .as_ref()
+            .filter(|_| build.upstream_published)
+            .map(|dir| dir.join(format!("{}-{}.json", build.name, build.upstream_version)));
+
+        let json_path = match cached {
+            Some(cached) if cached.exists() => cached,
+            cached => {
+                let json_path = match rustdoc()
+                    .toolchain(&self.breaking.toolchain)
+                    .quiet(true)
+                    .silent(self.silent)
+                    .manifest_path(&build.upstream_manifest_path)
+                    .build()
+                {
+                    Ok(json_path) => json_path,
+                    // old releases may not build on today's toolchain, don't let that stop
+                    // the rest of the workspace from being checked
+                    Err(e) => return Ok(Some(unbuildable_baseline(build, e.into()))),
+                };
+
+                if let Some(cached) = cached {
+                    // rename so an interrupted copy never leaves a broken entry behind, the
+                    // temporary file is unique so concurrent runs can't write to the same one
+                    let dir = cached.parent().context("no parent")?;
+                    let tmp = tempfile::NamedTempFile::new_in(dir)?;
+                    std::fs::copy(&json_path, tmp.path())?;
+                    tmp.persist(&cached)?;
+                }
+
+                self.keep_json(&json_path, &build.name, "old")?
+            }
+        };
 
-        let path = c.root().strip_prefix(workspace.root()).unwrap();
         let old = cargo_semver_checks::Rustdoc::from_path(&json_path);
         let old_diff = public_api::Builder::from_rustdoc_json(&json_path).build()?;
         let report = new
@@ -276,30 +611,28 @@ pub fn get_changes(
 
         let mut dep_bump = BumpKind::None;
 
-        for change in dep_changes {
-            if change.name == c.name().as_str() {
-                dep_bump = BumpKind::Minor;
-
-                let mut old = old_diff
-                    .items()
-                    .flat_map(|i| i.tokens())
-                    .filter_map(|t| match t {
-                        Token::Identifier(t) => Some(t),
-                        _ => None,
-                    });
-                let mut new = new_diff
-                    .items()
-                    .flat_map(|i| i.tokens())
-                    .filter_map(|t| match t {
-                        Token::Identifier(t) => Some(t),
-                        _ => None,
-                    });
-
-                if old.any(|t| *t == change.dep) && new.any(|t| *t == change.dep) {
-                    if change.breaking {
-                        dep_bump = BumpKind::Major;
-                        break;
-                    }
+        for change in &build.dep_changes {
+            dep_bump = BumpKind::Minor;
+
+            let mut old = old_diff
+                .items()
+                .flat_map(|i| i.tokens())
+                .filter_map(|t| match t {
+                    Token::Identifier(t) => Some(t),
+                    _ => None,
+                });
+            let mut new = new_diff
+                .items()
+                .flat_map(|i| i.tokens())
+                .filter_map(|t| match t {
+                    Token::Identifier(t) => Some(t),
+                    _ => None,
+                });
+
+            if old.any(|t| *t == change.dep) && new.any(|t| *t == change.dep) {
+                if change.breaking {
+                    dep_bump = BumpKind::Major;
+                    break;
                 }
             }
         }
@@ -326,21 +659,40 @@ pub fn get_changes(
         let bump = bump.max(dep_bump);
 
         debug!("-- semver --");
-        debug!("semver: {}", c.name());
+        debug!("semver: {}", build.name);
         debug!("required bump: {:?}", report.required_bump());
         debug!("adjusted bump: {}", bump);
 
-        if bump != BumpKind::None && (!breaking.major || bump == BumpKind::Major) {
-            changes.push(Change {
-                name: c.name().to_string(),
-                path: path.to_owned(),
+        if bump != BumpKind::None && (!self.breaking.major || bump == BumpKind::Major) {
+            Ok(Some(Change {
+                name: build.name.clone(),
+                path: build.path.clone(),
                 bump,
                 diff,
-            });
+                baseline_error: None,
+            }))
+        } else {
+            Ok(None)
         }
     }
+}
 
-    Ok(changes)
+fn unbuildable_baseline(build: &Build, err: anyhow::Error) -> Change {
+    debug!(
+        "failed to build {}-{}: {:?}",
+        build.name, build.upstream_version, err
+    );
+    Change {
+        name: build.name.clone(),
+        path: build.path.clone(),
+        bump: BumpKind::Major,
+        diff: PublicApiDiff {
+            removed: Vec::new(),
+            changed: Vec::new(),
+            added: Vec::new(),
+        },
+        baseline_error: Some(format!("{:#}", err)),
+    }
 }
 
 pub fn fmt_change(s: &PublicItem) -> String {
@@ -371,6 +723,16 @@ pub fn fmt_change(s: &PublicItem) -> String {
     ret.trim().to_string()
 }
 
+fn print_additions(args: &Args, c: &Change) -> Result<()> {
+    let mut stdout = args.stdout();
+    stdout.set_color(ColorSpec::new().set_fg(Some(Color::Green)))?;
+    for item in &c.diff.added {
+        writeln!(stdout, "   +{}", fmt_change(item))?;
+    }
+    stdout.set_color(&ColorSpec::new())?;
+    Ok(())
+}
+
 pub fn print_diff(args: &Args, c: &Change) -> Result<()> {
     let mut stdout = args.stdout();
     if let Some(change) = c.diff.removed.first() {
@@ -389,3 +751,67 @@ pub fn print_diff(args: &Args, c: &Change) -> Result<()> {
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn change(name: &str, bump: BumpKind) -> Change {
+        Change {
+            name: name.to_string(),
+            path: PathBuf::from(name),
+            bump,
+            diff: PublicApiDiff {
+                removed: Vec::new(),
+                changed: Vec::new(),
+                added: Vec::new(),
+            },
+            baseline_error: None,
+        }
+    }
+
+    #[test]
+    fn fail_on_skips_changes_the_plan_covers() {
+        let planner: Planner = toml::from_str(
+            r#"
+[[crate]]
+name = "a"
+from = "1.2.0"
+to = "1.3.0"
+
+[[crate]]
+name = "b"
+from = "1.2.0"
+to = "1.2.1"
+
+[[crate]]
+name = "c"
+from = "0.4.1"
+to = "0.4.2"
+
+[[crate]]
+name = "d"
+from = "0.4.1"
+to = "0.5.0"
+
+[[crate]]
+name = "e"
+from = "1.0.0"
+to = "2.0.0"
+publish = false
+"#,
+        )
+        .unwrap();
+        let planner = Some(&planner);
+
+        assert!(bumped_by_plan(planner, &change("a", BumpKind::Minor)));
+        assert!(!bumped_by_plan(planner, &change("a", BumpKind::Major)));
+        assert!(!bumped_by_plan(planner, &change("b", BumpKind::Minor)));
+        assert!(bumped_by_plan(planner, &change("c", BumpKind::Minor)));
+        assert!(!bumped_by_plan(planner, &change("c", BumpKind::Major)));
+        assert!(bumped_by_plan(planner, &change("d", BumpKind::Major)));
+        assert!(!bumped_by_plan(planner, &change("e", BumpKind::Patch)));
+        assert!(!bumped_by_plan(planner, &change("f", BumpKind::Patch)));
+        assert!(!bumped_by_plan(None, &change("a", BumpKind::Patch)));
+    }
+}
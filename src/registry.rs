@@ -1,14 +1,20 @@
 use std::collections::HashSet;
+use std::fmt::Display;
 use std::task::Poll;
+use std::thread;
+use std::time::Duration;
 
-use anyhow::{anyhow, Result};
+use anyhow::{bail, Result};
 use cargo::sources::source::{QueryKind, Source};
 use cargo::sources::IndexSummary;
 use cargo::{
-    core::{Dependency, SourceId, Workspace},
+    core::{Dependency, Package, SourceId, Workspace},
+    ops::RegistryOrIndex,
     sources::RegistrySource,
     util::interning::InternedString,
 };
+use log::debug;
+use semver::Version;
 
 pub fn get_registry<'a>(workspace: &Workspace<'a>) -> Result<RegistrySource<'a>> {
     let whitelist = workspace.members().map(|c| c.package_id()).collect();
@@ -20,22 +26,138 @@ pub fn get_registry<'a>(workspace: &Workspace<'a>) -> Result<RegistrySource<'a>>
     Ok(reg)
 }
 
+/// The registry crates are published to, crates.io unless another one is given.
+pub fn get_publish_registry<'a>(
+    workspace: &Workspace<'a>,
+    reg_or_index: Option<&RegistryOrIndex>,
+) -> Result<RegistrySource<'a>> {
+    let whitelist = workspace.members().map(|c| c.package_id()).collect();
+    let config = workspace.gctx();
+
+    let source_id = match reg_or_index {
+        None => return get_registry(workspace),
+        Some(RegistryOrIndex::Registry(name)) => SourceId::alt_registry(config, name)?,
+        Some(RegistryOrIndex::Index(url)) => SourceId::for_registry(url)?,
+    };
+
+    let mut reg = RegistrySource::remote(source_id, &whitelist, config)?;
+    reg.invalidate_cache();
+
+    Ok(reg)
+}
+
+/// Times a registry lookup that errors is tried before giving up on it.
+const QUERY_ATTEMPTS: u32 = 4;
+
+/// Times a pending lookup is waited for. Looking up alternative spellings of a name that isn't
+/// in the index can take a few rounds.
+const QUERY_POLLS: u32 = 16;
+
+/// The error returned by [`get_crate`] when the registry has no such crate, as opposed to a lookup
+/// that kept failing.
+#[derive(Debug)]
+pub struct NotFound(pub String);
+
+impl Display for NotFound {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} not found in the registry", self.0)
+    }
+}
+
+impl std::error::Error for NotFound {}
+
+/// Whether `err` means the crate doesn't exist rather than the lookup failing.
+pub fn is_not_found(err: &anyhow::Error) -> bool {
+    err.downcast_ref::<NotFound>().is_some()
+}
+
+/// All published versions of a crate.
+///
+/// A busy sparse index can leave queries pending or fail them with network errors, so those are
+/// retried a few times before giving up. A crate the registry doesn't have is a [`NotFound`] error.
 pub fn get_crate(reg: &mut RegistrySource, name: InternedString) -> Result<Vec<IndexSummary>> {
-    match reg.query_vec(
-        &Dependency::parse(name, None, reg.source_id())?,
-        QueryKind::Alternatives,
-    )? {
-        Poll::Ready(c) if c.is_empty() => Err(anyhow!("not found")),
-        Poll::Ready(c) => Ok(c),
-        Poll::Pending => Err(anyhow!("pending")),
+    let dep = Dependency::parse(name, None, reg.source_id())?;
+    let mut errors = 0;
+    let mut polls = 0;
+
+    loop {
+        let res = match reg.query_vec(&dep, QueryKind::Alternatives) {
+            Poll::Ready(res) => res.map(Some),
+            Poll::Pending => reg.block_until_ready().map(|()| None),
+        };
+
+        match res {
+            Ok(Some(c)) if c.is_empty() => return Err(NotFound(name.to_string()).into()),
+            Ok(Some(c)) => return Ok(c),
+            // the pending query has been waited for, poll it again straight away
+            Ok(None) => {
+                polls += 1;
+                if polls == QUERY_POLLS {
+                    bail!("failed to look up {}, the query is still pending", name);
+                }
+            }
+            Err(e) => {
+                errors += 1;
+                if errors == QUERY_ATTEMPTS {
+                    return Err(e.context(format!(
+                        "failed to look up {} after {} attempts",
+                        name, QUERY_ATTEMPTS
+                    )));
+                }
+                debug!("looking up {} failed, retrying: {:#}", name, e);
+                thread::sleep(Duration::from_millis(250 << errors));
+            }
+        }
     }
 }
 
+/// Whether a published version can be considered the current release of a crate.
+///
+/// Yanked versions never are. Prereleases only count when `pre` is set.
+pub fn is_release(version: &Version, yanked: bool, pre: bool) -> bool {
+    !yanked && (pre || version.pre.is_empty())
+}
+
+/// Whether `version` is published and not yanked. A yanked version doesn't count as apply tries
+/// publishing over it.
+pub fn has_version(crates: &[IndexSummary], version: &Version) -> bool {
+    crates
+        .iter()
+        .any(|c| !c.is_yanked() && c.as_summary().version() == version)
+}
+
+/// Picks the current release out of all published versions of a crate.
+pub fn latest_release(crates: &[IndexSummary], pre: bool) -> Option<&IndexSummary> {
+    crates
+        .iter()
+        .filter(|c| is_release(c.as_summary().version(), c.is_yanked(), pre))
+        .max_by_key(|c| c.as_summary().version())
+}
+
+/// Starts looking up a crate without waiting for it, so lookups can happen together.
+fn queue_crate(reg: &mut RegistrySource, name: InternedString) {
+    if let Ok(dep) = Dependency::parse(name, None, reg.source_id()) {
+        let _ = reg.query_vec(&dep, QueryKind::Alternatives);
+    }
+}
+
+/// Looks up `crates` together, for when only some of the members are wanted.
+pub fn download_packages<'a>(
+    reg: &mut RegistrySource,
+    crates: impl IntoIterator<Item = &'a Package>,
+) -> Result<()> {
+    for c in crates {
+        queue_crate(reg, c.name());
+    }
+    reg.block_until_ready()?;
+    Ok(())
+}
+
 pub fn download_crates(reg: &mut RegistrySource, workspace: &Workspace, deps: bool) -> Result<()> {
     let mut seen = HashSet::new();
 
     for c in workspace.members().filter(|c| c.publish().is_none()) {
-        let _ = get_crate(reg, c.name());
+        queue_crate(reg, c.name());
         seen.insert(c.name());
     }
 
@@ -44,7 +166,7 @@ pub fn download_crates(reg: &mut RegistrySource, workspace: &Workspace, deps: bo
             for dep in cra.dependencies() {
                 if dep.source_id().is_git() || dep.source_id().is_path() {
                     if !seen.contains(dep.package_name().as_str()) {
-                        let _ = get_crate(reg, dep.package_name());
+                        queue_crate(reg, dep.package_name());
                     }
                 }
             }
@@ -54,3 +176,67 @@ pub fn download_crates(reg: &mut RegistrySource, workspace: &Workspace, deps: bo
     reg.block_until_ready()?;
     Ok(())
 }
+
+#[cfg(test)]
+pub mod test {
+    use std::collections::BTreeMap;
+
+    use cargo::core::{PackageId, Summary};
+
+    use super::*;
+
+    /// The index entry of a published version, `!` marking it as yanked.
+    pub fn summary(name: &str, version: &str) -> IndexSummary {
+        let (version, yanked) = match version.strip_suffix('!') {
+            Some(version) => (version, true),
+            None => (version, false),
+        };
+        let sid =
+            SourceId::from_url("registry+https://github.com/rust-lang/crates.io-index").unwrap();
+        let id = PackageId::try_new(name, version, sid).unwrap();
+        let summary = Summary::new(id, Vec::new(), &BTreeMap::new(), None::<&str>, None).unwrap();
+        if yanked {
+            IndexSummary::Yanked(summary)
+        } else {
+            IndexSummary::Candidate(summary)
+        }
+    }
+
+    fn latest(versions: &[&str], pre: bool) -> Option<String> {
+        let crates = versions
+            .iter()
+            .map(|v| summary("foo", v))
+            .collect::<Vec<_>>();
+        latest_release(&crates, pre).map(|c| c.as_summary().version().to_string())
+    }
+
+    #[test]
+    fn prerelease_max_version() {
+        let versions = ["1.0.0", "1.1.0", "2.0.0-rc.1"];
+        assert_eq!(latest(&versions, false).as_deref(), Some("1.1.0"));
+        assert_eq!(latest(&versions, true).as_deref(), Some("2.0.0-rc.1"));
+        assert_eq!(latest(&["2.0.0-rc.1"], false), None);
+    }
+
+    #[test]
+    fn yanked_is_not_a_release() {
+        assert_eq!(
+            latest(&["1.0.0", "1.1.0!", "2.0.0-rc.1!"], true).as_deref(),
+            Some("1.0.0")
+        );
+        assert_eq!(latest(&["1.0.0!"], false), None);
+
+        let version = Version::parse("2.0.0-rc.1").unwrap();
+        assert!(is_release(&version, false, true));
+        assert!(!is_release(&version, false, false));
+        assert!(!is_release(&version, true, true));
+    }
+
+    #[test]
+    fn yanked_version_is_not_published() {
+        let crates = [summary("foo", "1.0.0"), summary("foo", "1.0.1!")];
+        assert!(has_version(&crates, &Version::parse("1.0.0").unwrap()));
+        assert!(!has_version(&crates, &Version::parse("1.0.1").unwrap()));
+        assert!(!has_version(&crates, &Version::parse("1.0.2").unwrap()));
+    }
+}
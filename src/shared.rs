@@ -1,16 +1,35 @@
 use std::{
+    collections::BTreeMap,
     env,
-    io::{stdin, BufRead},
-    sync::Arc,
-    time::Duration,
+    fs::{create_dir_all, read_to_string},
+    future::Future,
+    io::{stdin, BufRead, Read},
+    path::PathBuf,
+    process::{Command, Output, Stdio},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
 };
 
-use anyhow::Result;
-use cargo::core::Workspace;
-use crates_io_api::AsyncClient;
+use anyhow::{bail, Context, Result};
+use cargo::core::{Package, Workspace};
+use crates_io_api::{AsyncClient, CrateResponse, User};
 use futures::future::join_all;
+use log::debug;
+use reqwest::StatusCode;
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::cli::{Args, CacheArgs};
 
 const PARITY_CRATE_OWNER_ID: u64 = 150167;
+/// crates.io asks crawlers for at most one request per second.
+const CRATESIO_RATE_LIMIT_MS: u64 = 1000;
+const CRATESIO_RETRIES: u32 = 3;
+
+static GIT_TIMEOUT_SECS: AtomicU64 = AtomicU64::new(600);
 
 #[derive(Clone)]
 pub enum Owner {
@@ -19,14 +38,114 @@ pub enum Owner {
     Other,
 }
 
-pub fn read_stdin(args: &mut Vec<String>) -> Result<()> {
-    if let Some(n) = args.iter().position(|a| a == "-") {
-        let stdin = stdin().lock();
+/// Whether a member was excluded with `--exclude-members`.
+pub fn is_excluded(args: &Args, workspace: &Workspace, c: &Package) -> bool {
+    let path = c.root().strip_prefix(workspace.root()).unwrap_or(c.root());
+    args.exclude_members.iter().any(|p| p.matches_path(path))
+}
+
+/// The workspace members that are not excluded with `--exclude-members`.
+pub fn members<'a>(
+    args: &'a Args,
+    workspace: &'a Workspace<'_>,
+) -> impl Iterator<Item = &'a Package> + 'a {
+    workspace
+        .members()
+        .filter(move |c| !is_excluded(args, workspace, c))
+}
+
+/// Maps workspace member names to their packages, erroring if two members share a name.
+pub fn workspace_crates<'a>(workspace: &'a Workspace) -> Result<BTreeMap<&'a str, &'a Package>> {
+    let mut crates = BTreeMap::new();
+
+    for c in workspace.members() {
+        if let Some(other) = crates.insert(c.name().as_str(), c) {
+            bail!(
+                "multiple workspace members are named {}: {} and {}",
+                c.name(),
+                other.root().display(),
+                c.root().display()
+            );
+        }
+    }
+
+    Ok(crates)
+}
+
+pub fn set_git_timeout(secs: u64) {
+    GIT_TIMEOUT_SECS.store(secs, Ordering::Relaxed);
+}
+
+/// Runs a git command, killing it if it takes longer than `--git-timeout`.
+///
+/// `op` names the git operation in the error so it's clear what got stuck.
+pub fn git(cmd: &mut Command, op: &str) -> Result<Output> {
+    let secs = GIT_TIMEOUT_SECS.load(Ordering::Relaxed);
+    let mut child = cmd
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("failed to run git {}", op))?;
+
+    // read the pipes as we go so a chatty git can't block on a full pipe
+    let mut stdout = child.stdout.take().unwrap();
+    let mut stderr = child.stderr.take().unwrap();
+    let stdout = thread::spawn(move || {
+        let mut buf = Vec::new();
+        stdout.read_to_end(&mut buf).map(|_| buf)
+    });
+    let stderr = thread::spawn(move || {
+        let mut buf = Vec::new();
+        stderr.read_to_end(&mut buf).map(|_| buf)
+    });
+
+    let start = Instant::now();
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if secs != 0 && start.elapsed() > Duration::from_secs(secs) {
+            let _ = child.kill();
+            let _ = child.wait();
+            bail!("git {} timed out after {}s", op, secs);
+        }
+        thread::sleep(Duration::from_millis(50));
+    };
+
+    Ok(Output {
+        status,
+        stdout: stdout.join().unwrap()?,
+        stderr: stderr.join().unwrap()?,
+    })
+}
+
+/// Replaces a `-` in any of the given crate lists with the names read from stdin, one per
+/// line. Stdin can only be read once so at most one `-` may be given across all lists.
+pub fn read_stdin(lists: &mut [&mut Vec<String>]) -> Result<()> {
+    let dashes = lists
+        .iter()
+        .map(|l| l.iter().filter(|a| *a == "-").count())
+        .sum::<usize>();
+    if dashes > 1 {
+        bail!("`-` can only be given once as stdin can only be read once");
+    }
+
+    for args in lists.iter_mut() {
+        if let Some(n) = args.iter().position(|a| a == "-") {
+            let stdin = stdin().lock();
 
-        let lines = stdin.lines().collect::<std::result::Result<Vec<_>, _>>()?;
-        let rest = args.split_off(n);
-        args.extend(lines);
-        args.extend(rest.into_iter().skip(1));
+            let lines = stdin.lines().collect::<std::result::Result<Vec<_>, _>>()?;
+            let rest = args.split_off(n);
+            args.extend(
+                lines
+                    .iter()
+                    .map(|l| l.trim())
+                    .filter(|l| !l.is_empty())
+                    .map(|l| l.to_string()),
+            );
+            args.extend(rest.into_iter().skip(1));
+        }
     }
     Ok(())
 }
@@ -39,34 +158,217 @@ pub fn parity_crate_owner_id() -> u64 {
 }
 
 pub fn cratesio() -> Result<AsyncClient> {
+    let rate_limit = env::var("PARITY_PUBLISH_CRATESIO_RATE_LIMIT_MS")
+        .ok()
+        .and_then(|var| var.parse().ok())
+        .unwrap_or(CRATESIO_RATE_LIMIT_MS);
+
     Ok(AsyncClient::new(
         &format!("{}/{}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION")),
-        Duration::from_millis(0),
+        Duration::from_millis(rate_limit),
     )?)
 }
 
+/// Retries a crates.io request when it was throttled or hit a server error.
+async fn retry<T, F, Fut>(mut fetch: F) -> Result<T, crates_io_api::Error>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, crates_io_api::Error>>,
+{
+    let mut delay = Duration::from_secs(1);
+
+    for _ in 0..CRATESIO_RETRIES {
+        match fetch().await {
+            Err(crates_io_api::Error::Http(e))
+                if e.is_timeout()
+                    || e.is_connect()
+                    || e.status().is_some_and(|s| {
+                        s == StatusCode::TOO_MANY_REQUESTS || s.is_server_error()
+                    }) =>
+            {
+                debug!("crates.io request failed, retrying in {:?}: {}", delay, e);
+                tokio::time::sleep(delay).await;
+                delay *= 2;
+            }
+            res => return res,
+        }
+    }
+
+    fetch().await
+}
+
+/// On disk cache of crates.io responses keyed by crate name.
+pub struct Cache {
+    dir: Option<PathBuf>,
+    ttl: Duration,
+    refresh: bool,
+}
+
+impl Cache {
+    pub fn new(args: &CacheArgs) -> Self {
+        Cache {
+            dir: args.cache.then(cache_dir),
+            ttl: Duration::from_secs(args.cache_ttl),
+            refresh: args.refresh,
+        }
+    }
+
+    pub fn disabled() -> Self {
+        Cache {
+            dir: None,
+            ttl: Duration::ZERO,
+            refresh: false,
+        }
+    }
+
+    fn path(&self, kind: &str, name: &str) -> Option<PathBuf> {
+        self.dir
+            .as_ref()
+            .map(|dir| dir.join(format!("{}.{}.json", name, kind)))
+    }
+
+    fn read<T: DeserializeOwned>(&self, kind: &str, name: &str) -> Option<Option<T>> {
+        if self.refresh {
+            return None;
+        }
+
+        let path = self.path(kind, name)?;
+        let age = path.metadata().ok()?.modified().ok()?.elapsed().ok()?;
+        if age > self.ttl {
+            return None;
+        }
+
+        serde_json::from_str(&read_to_string(path).ok()?).ok()
+    }
+
+    fn write<T: Serialize>(&self, kind: &str, name: &str, value: &Option<T>) -> Result<()> {
+        let Some(path) = self.path(kind, name) else {
+            return Ok(());
+        };
+
+        create_dir_all(path.parent().unwrap())?;
+        std::fs::write(path, serde_json::to_string(value)?)?;
+        Ok(())
+    }
+
+    // Crates that don't exist are cached as null so they don't get looked up every run.
+    async fn get<T, F, Fut>(&self, kind: &str, name: &str, fetch: F) -> Result<Option<T>>
+    where
+        T: Serialize + DeserializeOwned,
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, crates_io_api::Error>>,
+    {
+        if let Some(value) = self.read(kind, name) {
+            return Ok(value);
+        }
+
+        let value = match retry(fetch).await {
+            Ok(value) => Some(value),
+            Err(crates_io_api::Error::NotFound(_)) => None,
+            Err(e) => return Err(e.into()),
+        };
+
+        // the lookup succeeded, a cache that can't be written, e.g. a read only HOME, only costs
+        // the next run another lookup
+        if let Err(e) = self.write(kind, name, &value) {
+            debug!("failed to cache {} for {}: {:#}", kind, name, e);
+        }
+        Ok(value)
+    }
+}
+
+pub fn cache_dir() -> PathBuf {
+    if let Some(dir) = env::var_os("PARITY_PUBLISH_CACHE_DIR") {
+        PathBuf::from(dir)
+    } else if let Some(dir) = env::var_os("XDG_CACHE_HOME") {
+        PathBuf::from(dir).join(env!("CARGO_PKG_NAME"))
+    } else if let Some(dir) = env::var_os("HOME") {
+        PathBuf::from(dir)
+            .join(".cache")
+            .join(env!("CARGO_PKG_NAME"))
+    } else {
+        env::temp_dir().join(env!("CARGO_PKG_NAME"))
+    }
+}
+
+pub async fn crate_owners(
+    cratesio: &AsyncClient,
+    cache: &Cache,
+    name: &str,
+) -> Result<Option<Vec<User>>> {
+    cache
+        .get("owners", name, || cratesio.crate_owners(name))
+        .await
+}
+
+pub async fn get_crate(
+    cratesio: &AsyncClient,
+    cache: &Cache,
+    name: &str,
+) -> Result<Option<CrateResponse>> {
+    cache.get("crate", name, || cratesio.get_crate(name)).await
+}
+
+/// The owners of each member not excluded with `--exclude-members`, in [`members`] order.
 pub async fn get_owners(
+    args: &Args,
     workspace: &Workspace<'_>,
     cratesio: &Arc<crates_io_api::AsyncClient>,
+    cache: &Cache,
 ) -> Vec<Owner> {
-    let owners = workspace
-        .members()
+    let owners = members(args, workspace)
         .map(|c| {
             let name = c.name().to_string();
             let cio = Arc::clone(cratesio);
-            async move { cio.crate_owners(&name).await }
+            async move { crate_owners(&cio, cache, &name).await }
         })
         .collect::<Vec<_>>();
     let owners = join_all(owners).await;
-    let owners = owners
+    owners.into_iter().map(owner).collect()
+}
+
+/// Who owns a crate going by its crates.io owners. Failed lookups count as unowned.
+pub fn owner(owners: Result<Option<Vec<User>>>) -> Owner {
+    match owners {
+        Err(_) | Ok(None) => Owner::None,
+        Ok(Some(v)) if v.iter().any(|user| user.id == parity_crate_owner_id()) => Owner::Us,
+        _ => Owner::Other,
+    }
+}
+
+/// Reads the files rewriting can touch: the manifests and READMEs of the workspace.
+///
+/// Files that don't exist are recorded as `None` so restoring removes them again.
+pub fn snapshot_files(workspace: &Workspace) -> Result<Vec<(PathBuf, Option<String>)>> {
+    let mut files = vec![workspace.root_manifest().to_path_buf()];
+    for c in workspace.members() {
+        files.push(c.manifest_path().to_path_buf());
+        files.push(c.root().join("README.md"));
+    }
+
+    files
         .into_iter()
-        .map(|o| match o {
-            Err(_) => Owner::None,
-            Ok(v) if v.iter().any(|user| user.id == parity_crate_owner_id()) => Owner::Us,
-            _ => Owner::Other,
+        .map(|path| {
+            let contents = if path.exists() {
+                Some(std::fs::read_to_string(&path)?)
+            } else {
+                None
+            };
+            Ok((path, contents))
         })
-        .collect();
-    owners
+        .collect()
+}
+
+/// Puts files back the way [`snapshot_files`] found them.
+pub fn restore_files(files: &[(PathBuf, Option<String>)]) -> Result<()> {
+    for (path, contents) in files {
+        match contents {
+            Some(contents) => std::fs::write(path, contents)?,
+            None if path.exists() => std::fs::remove_file(path)?,
+            None => (),
+        }
+    }
+    Ok(())
 }
 
 pub fn is_default<T: Default + PartialEq>(t: &T) -> bool {
@@ -80,3 +382,55 @@ pub fn is_not_default<T: Default + PartialEq>(t: &T) -> bool {
 pub fn bool_true() -> bool {
     true
 }
+
+#[cfg(test)]
+pub mod test {
+    use std::path::Path;
+
+    use cargo::GlobalContext;
+    use tempfile::TempDir;
+
+    /// Writes `files` under a new temporary directory, for tests that need a workspace on disk.
+    pub fn write_tree(files: &[(&str, &str)]) -> TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        for (path, contents) in files {
+            let path = dir.path().join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, contents).unwrap();
+        }
+        dir
+    }
+
+    /// Runs git in `dir`, returning what it printed.
+    pub fn git(dir: &Path, args: &[&str]) -> String {
+        let output = std::process::Command::new("git")
+            .arg("-C")
+            .arg(dir)
+            .args(["-c", "user.name=test", "-c", "user.email=test@test"])
+            .args(args)
+            .output()
+            .unwrap();
+        assert!(
+            output.status.success(),
+            "{}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        String::from_utf8(output.stdout).unwrap().trim().to_string()
+    }
+
+    /// Makes `dir` a git repo with everything in it committed, returning the commit.
+    pub fn commit_all(dir: &Path) -> String {
+        if !dir.join(".git").exists() {
+            git(dir, &["init", "-q"]);
+        }
+        git(dir, &["add", "-A"]);
+        git(dir, &["commit", "-q", "--allow-empty", "-m", "commit"]);
+        git(dir, &["rev-parse", "HEAD"])
+    }
+
+    pub fn gctx() -> GlobalContext {
+        let gctx = GlobalContext::default().unwrap();
+        gctx.shell().set_verbosity(cargo::core::Verbosity::Quiet);
+        gctx
+    }
+}
@@ -1,12 +1,27 @@
-use crate::cli::{Args, Status};
-use crate::shared::{self, parity_crate_owner_id};
+use crate::cli::{Args, Status, StatusSort};
+use crate::registry;
+use crate::shared::{self, parity_crate_owner_id, Cache};
 
 use anyhow::Result;
-use cargo::core::Workspace;
-use std::env::current_dir;
+use cargo::core::{Package, Workspace};
+use semver::Version;
 use std::io::Write;
 use termcolor::{Color, ColorSpec, WriteColor};
 
+struct Upstream {
+    latest: Option<Version>,
+    /// The newest version if it has been yanked
+    yanked: Option<Version>,
+    versions_match: bool,
+    parity_own: bool,
+}
+
+struct Row<'a> {
+    member: &'a Package,
+    /// None if the crate is not on crates.io
+    upstream: Option<Upstream>,
+}
+
 fn color_ok_red(stdout: &mut impl WriteColor, ok: bool, color: Color) -> Result<()> {
     if ok {
         stdout.set_color(ColorSpec::new().set_fg(Some(Color::Green)))?;
@@ -19,11 +34,12 @@ fn color_ok_red(stdout: &mut impl WriteColor, ok: bool, color: Color) -> Result<
 
 pub async fn handle_status(args: Args, status: Status) -> Result<()> {
     let config = cargo::GlobalContext::default()?;
-    let path = current_dir()?.join("Cargo.toml");
+    let path = args.manifest_path()?;
     let workspace = Workspace::new(&path, &config)?;
-    let members = workspace.members();
+    let members = shared::members(&args, &workspace);
 
     let cratesio = shared::cratesio()?;
+    let cache = Cache::new(&status.cache);
 
     let mut stdout = args.stdout();
     let mut stderr = args.stderr();
@@ -32,12 +48,14 @@ pub async fn handle_status(args: Args, status: Status) -> Result<()> {
         stderr.set_color(ColorSpec::new().set_bold(true))?;
         writeln!(
             stderr,
-            "{:<50}{:<16}{:<16}{:<0}",
-            "Crate", "Local Ver", "crates.io Ver", "Owner"
+            "{:<50}{:<16}{:<16}{:<16}{:<0}",
+            "Crate", "Local Ver", "crates.io Ver", "Yanked", "Owner"
         )?;
         stderr.set_color(ColorSpec::new().set_bold(false))?;
     }
 
+    let mut rows = Vec::new();
+
     for member in members {
         // crates may have no publish set because the current workflow doesn't involve publishing
         // to crates.io
@@ -46,15 +64,31 @@ pub async fn handle_status(args: Args, status: Status) -> Result<()> {
             continue;
         }
 
-        if let Ok(cra) = cratesio.full_crate(&member.name(), false).await {
+        let cra = shared::get_crate(&cratesio, &cache, &member.name()).await;
+        let owners = shared::crate_owners(&cratesio, &cache, &member.name()).await;
+
+        if let (Ok(Some(cra)), Ok(Some(owners))) = (cra, owners) {
             if status.missing {
                 continue;
             }
 
-            let versions_match = member.version().to_string().split('-').next().unwrap()
-                == cra.max_version.split('-').next().unwrap();
+            let pre = !member.version().pre.is_empty();
+            let latest = cra
+                .versions
+                .iter()
+                .filter_map(|v| Some((Version::parse(&v.num).ok()?, v.yanked)))
+                .filter(|(v, yanked)| registry::is_release(v, *yanked, pre))
+                .map(|(v, _)| v)
+                .max();
+            let yanked = cra
+                .versions
+                .iter()
+                .filter_map(|v| Some((Version::parse(&v.num).ok()?, v.yanked)))
+                .filter(|(v, _)| registry::is_release(v, false, pre))
+                .max_by(|(a, _), (b, _)| a.cmp(b))
+                .and_then(|(v, yanked)| yanked.then_some(v));
+            let versions_match = latest.as_ref() == Some(member.version());
 
-            let owners = cra.owners;
             let parity_own = owners.iter().any(|user| user.id == parity_crate_owner_id());
 
             if status.external && parity_own {
@@ -63,6 +97,54 @@ pub async fn handle_status(args: Args, status: Status) -> Result<()> {
             if status.version && versions_match {
                 continue;
             }
+            if status.yanked && yanked.is_none() {
+                continue;
+            }
+
+            rows.push(Row {
+                member,
+                upstream: Some(Upstream {
+                    latest,
+                    yanked,
+                    versions_match,
+                    parity_own,
+                }),
+            });
+        } else if !status.yanked {
+            rows.push(Row {
+                member,
+                upstream: None,
+            });
+        }
+    }
+
+    match status.sort {
+        None => (),
+        Some(StatusSort::Name) => rows.sort_by_key(|r| r.member.name()),
+        Some(StatusSort::Owner) => rows.sort_by_key(|r| match &r.upstream {
+            Some(u) if u.parity_own => 0,
+            Some(_) => 1,
+            None => 2,
+        }),
+        Some(StatusSort::Version) => rows.sort_by_key(|r| r.member.version()),
+        Some(StatusSort::Mismatch) => {
+            rows.sort_by_key(|r| r.upstream.as_ref().is_some_and(|u| u.versions_match))
+        }
+    }
+
+    for row in rows {
+        let member = row.member;
+
+        if let Some(upstream) = row.upstream {
+            let versions_match = upstream.versions_match;
+            let parity_own = upstream.parity_own;
+            let latest = upstream
+                .latest
+                .map_or_else(|| "None".to_string(), |v| v.to_string());
+            let yanked = upstream
+                .yanked
+                .as_ref()
+                .map_or_else(|| "No".to_string(), |v| v.to_string());
 
             if !parity_own {
                 stdout.set_color(ColorSpec::new().set_fg(Some(Color::Red)))?;
@@ -78,11 +160,16 @@ pub async fn handle_status(args: Args, status: Status) -> Result<()> {
             }
 
             if status.quiet {
+                stdout.set_color(ColorSpec::new().set_fg(None))?;
+                writeln!(stdout)?;
                 continue;
             }
 
             color_ok_red(&mut stdout, versions_match, Color::Yellow)?;
-            write!(stdout, "{:<16}{:<16}", member.version(), cra.max_version)?;
+            write!(stdout, "{:<16}{:<16}", member.version(), latest)?;
+
+            color_ok_red(&mut stdout, upstream.yanked.is_none(), Color::Yellow)?;
+            write!(stdout, "{:<16}", yanked)?;
 
             color_ok_red(&mut stdout, parity_own, Color::Red)?;
             if parity_own {
@@ -97,10 +184,11 @@ pub async fn handle_status(args: Args, status: Status) -> Result<()> {
             } else {
                 write!(
                     stdout,
-                    "{:<50}{:<16}{:<16}{:<0}",
+                    "{:<50}{:<16}{:<16}{:<16}{:<0}",
                     member.name(),
                     member.version(),
                     "Missing",
+                    "No",
                     "No One"
                 )?;
             }
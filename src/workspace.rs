@@ -1,20 +1,41 @@
 use crate::{
     cli::{self, Args},
-    shared::read_stdin,
+    plan, registry,
+    shared::{self, read_stdin},
 };
-use anyhow::Result;
-use cargo::core::Workspace;
-use std::{collections::HashSet, env::current_dir, io::Write, path::Path};
+use anyhow::{Context, Result};
+use cargo::{
+    core::{dependency::DepKind, FeatureValue, Workspace},
+    util::cache_lock::CacheLockMode,
+};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashSet, VecDeque},
+    fs::read_to_string,
+    io::Write,
+    path::{Component, Path, PathBuf},
+    process::exit,
+};
+use termcolor::{ColorSpec, WriteColor};
 
 pub fn handle_workspace(args: Args, mut cli: cli::Workspace) -> Result<()> {
-    read_stdin(&mut cli.targets)?;
+    read_stdin(&mut [&mut cli.targets])?;
     let config = cargo::GlobalContext::default()?;
     config.shell().set_verbosity(cargo::core::Verbosity::Quiet);
-    let path = current_dir()?.join("Cargo.toml");
+    let path = args.manifest_path()?;
     let workspace = Workspace::new(&path, &config)?;
 
     if cli.owns {
         owns(&args, cli, &workspace)?;
+    } else if cli.members_of_path {
+        members_of_path(&args, cli, &workspace)?;
+    } else if cli.unpublished {
+        unpublished(&args, cli, &workspace)?;
+    } else if cli.cycles {
+        cycles(&args, &workspace)?;
+    } else if cli.features {
+        features(&args, cli, &workspace)?;
+    } else if cli.deps {
+        deps(&args, cli, &workspace)?;
     } else {
         members(&args, cli, &workspace)?;
     }
@@ -27,12 +48,10 @@ fn owns(args: &Args, cli: cli::Workspace, w: &Workspace) -> Result<()> {
     let mut stderr = args.stderr();
     let mut seen = HashSet::new();
 
-    'outer: for targ in &cli.targets {
-        for c in w.members() {
-            if seen.contains(&c.name()) {
-                continue;
-            }
+    for targ in &cli.targets {
+        let mut owner = None;
 
+        for c in w.members() {
             let contains = if Path::new(targ) == c.root().strip_prefix(w.root()).unwrap()
                 || Path::new(targ) == c.manifest_path().strip_prefix(w.root()).unwrap()
             {
@@ -49,28 +68,377 @@ fn owns(args: &Args, cli: cli::Workspace, w: &Workspace) -> Result<()> {
             };
 
             if contains {
-                seen.insert(c.name());
-
-                if cli.quiet {
-                    writeln!(stdout, "{}", c.name(),)?;
-                } else {
-                    writeln!(
-                        stdout,
-                        "{} {}",
-                        c.name(),
-                        c.root().strip_prefix(w.root()).unwrap().display()
-                    )?;
-                }
-                continue 'outer;
+                owner = Some(c);
+                break;
+            }
+        }
+
+        // Fall back to the crate with the most specific root containing the target
+        let owner = owner.or_else(|| {
+            w.members()
+                .map(|c| (c, c.root().strip_prefix(w.root()).unwrap()))
+                .filter(|(_, root)| Path::new(targ).starts_with(root))
+                .max_by_key(|(_, root)| root.components().count())
+                .map(|(c, _)| c)
+        });
+
+        let Some(c) = owner else {
+            writeln!(stderr, "error: can't find owner for '{}'", targ)?;
+            continue;
+        };
+
+        // the owner is found among every member so files in an excluded member aren't
+        // attributed to a member containing it
+        if shared::is_excluded(args, w, c) || !seen.insert(c.name()) {
+            continue;
+        }
+
+        if cli.quiet {
+            writeln!(stdout, "{}", c.name(),)?;
+        } else {
+            writeln!(
+                stdout,
+                "{} {}",
+                c.name(),
+                c.root().strip_prefix(w.root()).unwrap().display()
+            )?;
+        }
+    }
+
+    Ok(())
+}
+
+fn members_of_path(args: &Args, cli: cli::Workspace, w: &Workspace) -> Result<()> {
+    let mut stdout = args.stdout();
+    let mut stderr = args.stderr();
+    let mut seen = HashSet::new();
+
+    for targ in &cli.targets {
+        let dir = Path::new(targ)
+            .components()
+            .filter(|c| *c != Component::CurDir)
+            .collect::<PathBuf>();
+        let mut found = false;
+
+        for c in shared::members(args, w) {
+            let root = c.root().strip_prefix(w.root()).unwrap();
+            if !root.starts_with(&dir) {
+                continue;
+            }
+
+            found = true;
+            if !seen.insert(c.name()) {
+                continue;
+            }
+
+            if cli.paths > 1 {
+                writeln!(stdout, "{}", root.join("Cargo.toml").display())?;
+            } else if cli.paths == 1 {
+                writeln!(stdout, "{}", root.display())?;
+            } else if cli.quiet {
+                writeln!(stdout, "{}", c.name())?;
+            } else {
+                writeln!(stdout, "{} {}", c.name(), root.display())?;
+            }
+        }
+
+        if !found {
+            writeln!(stderr, "error: no packages under '{}'", targ)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn unpublished(args: &Args, cli: cli::Workspace, w: &Workspace) -> Result<()> {
+    let mut stdout = args.stdout();
+
+    let _lock = w
+        .gctx()
+        .acquire_package_cache_lock(CacheLockMode::DownloadExclusive)?;
+    let mut reg = registry::get_registry(w)?;
+    args.progress("looking up crates...")?;
+    registry::download_crates(&mut reg, w, false)?;
+
+    for c in shared::members(args, w) {
+        if c.publish().is_some() {
+            continue;
+        }
+        if !cli.targets.is_empty() && !cli.targets.iter().any(|t| t == c.name().as_str()) {
+            continue;
+        }
+        match registry::get_crate(&mut reg, c.name()) {
+            Err(e) if registry::is_not_found(&e) => (),
+            Err(e) => return Err(e),
+            Ok(_) => continue,
+        }
+
+        let root = c.root().strip_prefix(w.root()).unwrap();
+        if cli.paths > 1 {
+            writeln!(stdout, "{}", root.join("Cargo.toml").display())?;
+        } else if cli.paths == 1 {
+            writeln!(stdout, "{}", root.display())?;
+        } else if cli.quiet {
+            writeln!(stdout, "{}", c.name())?;
+        } else {
+            writeln!(stdout, "{} {}", c.name(), root.display())?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Prints each feature of the target packages and the values it enables, parsed the way
+/// `remove_feature` and `remove_dep` see them.
+fn features(args: &Args, cli: cli::Workspace, w: &Workspace) -> Result<()> {
+    let mut stdout = args.stdout();
+
+    for targ in &cli.targets {
+        let c = w
+            .members()
+            .find(|c| c.name().as_str() == targ)
+            .with_context(|| format!("can't find package '{}'", targ))?;
+        let manifest: toml::Table = toml::from_str(&read_to_string(c.manifest_path())?)?;
+        let root = c.root().strip_prefix(w.root()).unwrap();
+
+        stdout.set_color(ColorSpec::new().set_bold(true))?;
+        write!(stdout, "{}", c.name())?;
+        stdout.set_color(ColorSpec::new().set_bold(false))?;
+        writeln!(stdout, " ({}):", root.display())?;
+
+        let features = manifest.get("features").and_then(|f| f.as_table());
+        let Some(features) = features.filter(|f| !f.is_empty()) else {
+            writeln!(stdout, "    no features")?;
+            writeln!(stdout)?;
+            continue;
+        };
+
+        for (feature, values) in features {
+            writeln!(stdout, "    {}", feature)?;
+
+            for value in values.as_array().into_iter().flatten() {
+                let Some(value) = value.as_str() else {
+                    continue;
+                };
+                let desc = match FeatureValue::new(value.into()) {
+                    FeatureValue::Feature(f) => format!("feature {}", f),
+                    FeatureValue::Dep { dep_name } => format!("dep {}", dep_name),
+                    FeatureValue::DepFeature {
+                        dep_name,
+                        dep_feature,
+                        weak,
+                    } => format!(
+                        "{}dep-feature {} of {}",
+                        if weak { "weak " } else { "" },
+                        dep_feature,
+                        dep_name
+                    ),
+                };
+                writeln!(stdout, "        {}", desc)?;
             }
         }
+        writeln!(stdout)?;
+    }
+
+    Ok(())
+}
+
+fn deps(args: &Args, cli: cli::Workspace, w: &Workspace) -> Result<()> {
+    let mut stdout = args.stdout();
+
+    for targ in &cli.targets {
+        let c = w
+            .members()
+            .find(|c| c.name().as_str() == targ)
+            .with_context(|| format!("can't find package '{}'", targ))?;
+        let manifest: toml::Table = toml::from_str(&read_to_string(c.manifest_path())?)?;
+        let root = c.root().strip_prefix(w.root()).unwrap();
 
-        writeln!(stderr, "error: can't find owner for '{}'", targ)?;
+        stdout.set_color(ColorSpec::new().set_bold(true))?;
+        write!(stdout, "{}", c.name())?;
+        stdout.set_color(ColorSpec::new().set_bold(false))?;
+        writeln!(stdout, " ({}):", root.display())?;
+
+        if c.dependencies().is_empty() {
+            writeln!(stdout, "    no dependencies")?;
+        }
+
+        for dep in c.dependencies() {
+            let source = dep.source_id();
+            let mut desc = if source.is_path() {
+                let path = source.url().to_file_path().unwrap_or_default();
+                format!(
+                    "path {}",
+                    path.strip_prefix(w.root()).unwrap_or(&path).display()
+                )
+            } else if source.is_git() {
+                format!("git {}", source.url())
+            } else if source.is_crates_io() {
+                "registry".to_string()
+            } else {
+                format!("registry {}", source.url())
+            };
+
+            if inherits_dep(&manifest, dep.kind(), dep.name_in_toml().as_str()) {
+                desc.push_str(" (workspace)");
+            }
+            match dep.kind() {
+                DepKind::Normal => (),
+                DepKind::Development => desc.push_str(" [dev]"),
+                DepKind::Build => desc.push_str(" [build]"),
+            }
+            if dep.is_optional() {
+                desc.push_str(" [optional]");
+            }
+            if dep.package_name() != dep.name_in_toml() {
+                desc.push_str(&format!(" [package {}]", dep.package_name()));
+            }
+
+            writeln!(
+                stdout,
+                "    {} {} {}",
+                dep.name_in_toml(),
+                dep.version_req(),
+                desc
+            )?;
+        }
+        writeln!(stdout)?;
     }
 
     Ok(())
 }
 
+/// Whether a dependency is declared with `workspace = true`, which cargo doesn't keep track of
+/// once the manifest is loaded.
+fn inherits_dep(manifest: &toml::Table, kind: DepKind, name: &str) -> bool {
+    let table = match kind {
+        DepKind::Normal => "dependencies",
+        DepKind::Development => "dev-dependencies",
+        DepKind::Build => "build-dependencies",
+    };
+    let targets = manifest
+        .get("target")
+        .and_then(|t| t.as_table())
+        .into_iter()
+        .flat_map(|t| t.values())
+        .filter_map(|t| t.as_table());
+
+    [manifest]
+        .into_iter()
+        .chain(targets)
+        .filter_map(|t| t.get(table))
+        .filter_map(|deps| deps.get(name))
+        .any(|dep| dep.get("workspace").and_then(|w| w.as_bool()) == Some(true))
+}
+
+/// Prints a dependency cycle through each group of members that depend on each other.
+///
+/// Exits 1 if there are any as they can't be published.
+fn cycles(args: &Args, w: &Workspace) -> Result<()> {
+    let mut stdout = args.stdout();
+    let graph = plan::dep_graph(args, w);
+    let mut tarjan = Tarjan {
+        graph: &graph,
+        index: BTreeMap::new(),
+        low: BTreeMap::new(),
+        stack: Vec::new(),
+        sccs: Vec::new(),
+    };
+
+    for name in graph.keys() {
+        if !tarjan.index.contains_key(name) {
+            tarjan.visit(name);
+        }
+    }
+
+    let cycles = tarjan
+        .sccs
+        .iter()
+        .filter(|scc| scc.len() > 1)
+        .map(|scc| shortest_cycle(&graph, scc))
+        .collect::<Vec<_>>();
+
+    if cycles.is_empty() {
+        writeln!(stdout, "no cycles")?;
+        return Ok(());
+    }
+
+    for cycle in cycles {
+        writeln!(stdout, "{} -> {}", cycle.join(" -> "), cycle[0])?;
+    }
+
+    exit(1);
+}
+
+/// Tarjan's algorithm for finding the strongly connected components of the dependency graph.
+struct Tarjan<'a, 'g> {
+    graph: &'g BTreeMap<&'a str, BTreeSet<&'a str>>,
+    index: BTreeMap<&'a str, usize>,
+    low: BTreeMap<&'a str, usize>,
+    stack: Vec<&'a str>,
+    sccs: Vec<BTreeSet<&'a str>>,
+}
+
+impl<'a> Tarjan<'a, '_> {
+    fn visit(&mut self, name: &'a str) {
+        let index = self.index.len();
+        self.index.insert(name, index);
+        self.low.insert(name, index);
+        self.stack.push(name);
+
+        for &dep in &self.graph[name] {
+            if !self.index.contains_key(dep) {
+                self.visit(dep);
+                let low = self.low[name].min(self.low[dep]);
+                self.low.insert(name, low);
+            } else if self.stack.contains(&dep) {
+                let low = self.low[name].min(self.index[dep]);
+                self.low.insert(name, low);
+            }
+        }
+
+        if self.low[name] == index {
+            let mut scc = BTreeSet::new();
+            while let Some(n) = self.stack.pop() {
+                scc.insert(n);
+                if n == name {
+                    break;
+                }
+            }
+            self.sccs.push(scc);
+        }
+    }
+}
+
+/// Finds the shortest cycle through the first crate of a group that all depend on each other.
+fn shortest_cycle<'a>(
+    graph: &BTreeMap<&'a str, BTreeSet<&'a str>>,
+    scc: &BTreeSet<&'a str>,
+) -> Vec<&'a str> {
+    let start = *scc.first().unwrap();
+    let mut parent = BTreeMap::new();
+    let mut queue = VecDeque::from([start]);
+
+    while let Some(name) = queue.pop_front() {
+        for &dep in graph[name].iter().filter(|d| scc.contains(*d)) {
+            if dep == start {
+                let mut cycle = vec![name];
+                while let Some(&p) = parent.get(cycle.last().unwrap()) {
+                    cycle.push(p);
+                }
+                cycle.reverse();
+                return cycle;
+            }
+            if !parent.contains_key(dep) {
+                parent.insert(dep, name);
+                queue.push_back(dep);
+            }
+        }
+    }
+
+    scc.iter().copied().collect()
+}
+
 fn members(args: &Args, cli: cli::Workspace, w: &Workspace) -> Result<()> {
     let mut stdout = args.stdout();
     let mut stderr = args.stderr();